@@ -0,0 +1,57 @@
+//! measure the cost of tearing down a manager with many sub coroutines
+//!
+//! run with `cargo bench --bench teardown`
+
+use co_managed::Manager;
+use may::{coroutine, go};
+
+use std::time::{Duration, Instant};
+
+const CHILDREN: usize = 10_000;
+const ROUNDS: usize = 5;
+
+// the user + system cpu time of the whole process
+fn cpu_time() -> Duration {
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    let tv = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    tv(usage.ru_utime) + tv(usage.ru_stime)
+}
+
+// return the (wall, cpu) time of dropping the manager
+fn teardown() -> (Duration, Duration) {
+    let manager = Manager::new();
+    for _ in 0..CHILDREN {
+        manager.add(|| loop {
+            coroutine::sleep(Duration::from_secs(1));
+        });
+    }
+    // wait all the sub coroutines started
+    while manager.children().count() < CHILDREN {
+        coroutine::sleep(Duration::from_millis(1));
+    }
+
+    let cpu = cpu_time();
+    let wall = Instant::now();
+    drop(manager);
+    (wall.elapsed(), cpu_time() - cpu)
+}
+
+fn report(name: &str, f: impl Fn() -> (Duration, Duration)) {
+    let (mut wall, mut cpu) = (Duration::ZERO, Duration::ZERO);
+    for _ in 0..ROUNDS {
+        let (w, c) = f();
+        wall += w;
+        cpu += c;
+    }
+    println!(
+        "{name:<24} {CHILDREN} children: wall {:>10.3?}, cpu {:>10.3?}",
+        wall / ROUNDS as u32,
+        cpu / ROUNDS as u32
+    );
+}
+
+fn main() {
+    report("drop in thread", teardown);
+    report("drop in coroutine", || go!(teardown).join().unwrap());
+}
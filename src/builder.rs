@@ -0,0 +1,59 @@
+use crate::{ManagedHandle, Manager};
+
+use std::io;
+
+/// sub coroutine factory, used to config the properties of a managed sub coroutine
+///
+/// created by `Manager::builder`
+///
+/// ```rust,no_run
+/// use co_managed::Manager;
+///
+/// let manager = Manager::new();
+/// let handle = manager
+///     .builder()
+///     .name("conn-42")
+///     .stack_size(64 * 1024)
+///     .spawn(|| 42)
+///     .unwrap();
+/// assert_eq!(handle.join().unwrap(), 42);
+/// ```
+pub struct Builder<'a> {
+    manager: &'a Manager,
+    name: Option<String>,
+    stack_size: Option<usize>,
+}
+
+impl<'a> Builder<'a> {
+    pub(crate) fn new(manager: &'a Manager) -> Self {
+        Builder {
+            manager,
+            name: None,
+            stack_size: None,
+        }
+    }
+
+    /// name the sub coroutine, the name is kept in the `ChildInfo`
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// set the stack size of the sub coroutine
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// spawn the sub coroutine with the config
+    ///
+    /// block the caller if the manager reached its capacity limit
+    pub fn spawn<F, T>(self, f: F) -> io::Result<ManagedHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.manager.acquire_slot();
+        self.manager.spawn_impl(self.name, self.stack_size, None, f)
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{Inner, Owner};
+use may::coroutine::Coroutine;
+use rcu_cell::RcuCell;
+
+use std::sync::atomic::{fence, AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// the state of a managed sub coroutine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildState {
+    /// the sub coroutine is running
+    Running,
+    /// the manager requested the sub coroutine to stop
+    Stopping,
+    /// the sub coroutine is cancelled and is unwinding
+    Cancelled,
+    /// the sub coroutine ran out of its time budget and is cancelled
+    TimedOut,
+}
+
+/// the information of a managed sub coroutine
+#[derive(Debug, Clone)]
+pub struct ChildInfo {
+    /// the unique id of the sub coroutine
+    pub id: usize,
+    /// the name of the sub coroutine
+    pub name: Option<String>,
+    /// when the sub coroutine is spawned
+    pub spawned_at: Instant,
+    /// the current state of the sub coroutine
+    pub state: ChildState,
+}
+
+// the cancel state of a child, a detached child can't be cancelled by the manager
+const RUNNING: u8 = 0;
+const CANCELLED: u8 = 1;
+const DETACHED: u8 = 2;
+
+// the sub coroutine data in the manager list
+pub(crate) struct Child {
+    id: usize,
+    name: Option<String>,
+    spawned_at: Instant,
+    // the deadline of the sub coroutine itself, without the manager one
+    deadline: Option<Instant>,
+    // the scoped child borrows from the scope, it can't leave its manager
+    scoped: bool,
+    // the cleanup is masked from the cancel of the manager, only its
+    // deadline cancels it, and it doesn't take a slot of the capacity limit
+    cleanup: bool,
+    co: RcuCell<Coroutine>,
+    state: AtomicU8,
+    timed_out: AtomicBool,
+    finished: AtomicBool,
+    // the manager that the child belongs to, taken by the exit or the detach
+    owner: Mutex<Option<Owner>>,
+    // the cleanups dropped after the cancel, in drop order
+    cleanups: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+    // the span that is current when the child is spawned
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Child {
+    pub fn new(name: Option<String>, deadline: Option<Instant>, scoped: bool) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+        Child {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            spawned_at: Instant::now(),
+            deadline,
+            scoped,
+            cleanup: false,
+            co: RcuCell::none(),
+            state: AtomicU8::new(RUNNING),
+            timed_out: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            owner: Mutex::new(None),
+            cleanups: Mutex::new(Vec::new()),
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
+        }
+    }
+
+    // the child that runs the cleanup of a cancelled sub coroutine
+    pub fn cleanup(deadline: Option<Instant>) -> Self {
+        Child {
+            cleanup: true,
+            ..Child::new(None, deadline, false)
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn spawned_at(&self) -> Instant {
+        self.spawned_at
+    }
+
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    pub fn owner(&self) -> MutexGuard<'_, Option<Owner>> {
+        self.owner.lock().unwrap()
+    }
+
+    // the manager that the child currently belongs to
+    pub fn manager(&self) -> Option<Arc<Inner>> {
+        self.owner().as_ref().map(|owner| owner.inner.clone())
+    }
+
+    pub fn is_scoped(&self) -> bool {
+        self.scoped
+    }
+
+    pub fn is_cleanup(&self) -> bool {
+        self.cleanup
+    }
+
+    // the earlier one of the child deadline and the manager deadline
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.cleanup {
+            return self.deadline;
+        }
+        let manager = self.manager().and_then(|inner| inner.deadline);
+        match (self.deadline, manager) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // the child is registered before the coroutine is spawned, a cancel
+    // that comes before the coroutine handle is set would be applied here
+    pub fn set_coroutine(&self, co: Coroutine) {
+        self.co.write(co);
+        fence(Ordering::SeqCst);
+        if self.state.load(Ordering::SeqCst) == CANCELLED {
+            self.cancel_co();
+        }
+    }
+
+    // return true only if the child is cancelled by this call, false if it's
+    // already cancelled, detached or is a cleanup
+    pub fn cancel(&self) -> bool {
+        if self.cleanup {
+            return false;
+        }
+        self.force_cancel()
+    }
+
+    fn force_cancel(&self) -> bool {
+        let first = match self.state.compare_exchange(
+            RUNNING,
+            CANCELLED,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        ) {
+            Ok(_) => true,
+            Err(DETACHED) => return false,
+            // cancel the coroutine again, it may be set after the first cancel
+            Err(_) => false,
+        };
+        fence(Ordering::SeqCst);
+        // notify before the cancel, a cancelled child may finish right away
+        if first && !self.cleanup && !self.finished.load(Ordering::Acquire) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                parent: &self.span,
+                id = self.id,
+                name = self.name().unwrap_or_default(),
+                "managed coroutine cancelled"
+            );
+            let hooks = self.manager().and_then(|inner| inner.hooks.read());
+            if let Some(on_cancel) = hooks.and_then(|h| h.on_cancel.clone()) {
+                on_cancel(&self.info(false));
+            }
+        }
+        self.cancel_co();
+        first
+    }
+
+    // take the child out of the manager, return false if it's already cancelled
+    // or it's scoped, the scope must join it before the borrowed data is gone
+    pub fn detach(&self) -> bool {
+        if self.scoped || self.cleanup {
+            return false;
+        }
+        let detached = self
+            .state
+            .compare_exchange(RUNNING, DETACHED, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok();
+        if detached {
+            // the child may exit at the same time and unregister itself
+            let owner = self.owner().take();
+            if let Some(owner) = owner {
+                owner.inner.stats.detach();
+                owner.release();
+            }
+        }
+        detached
+    }
+
+    // cancel the child when its deadline expires
+    pub fn time_out(&self) {
+        // the node may be kept by the handle after the child finished
+        if self.finished.load(Ordering::Acquire) {
+            return;
+        }
+        // the child may be moved to a manager with a later deadline
+        match self.deadline() {
+            Some(deadline) if deadline <= Instant::now() => {}
+            _ => return,
+        }
+        // set the flag before the cancel, the hooks would see it
+        self.timed_out.store(true, Ordering::SeqCst);
+        self.force_cancel();
+        if self.is_detached() {
+            self.timed_out.store(false, Ordering::SeqCst);
+        }
+    }
+
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.load(Ordering::Acquire) == CANCELLED
+    }
+
+    // the cancelled child can't block, its cleanups run after it exits
+    pub fn queue_cleanup(&self, f: Box<dyn FnOnce() + Send>) {
+        self.cleanups.lock().unwrap().push(f);
+    }
+
+    pub fn take_cleanups(&self) -> Vec<Box<dyn FnOnce() + Send>> {
+        std::mem::take(&mut *self.cleanups.lock().unwrap())
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.state.load(Ordering::Acquire) == DETACHED
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::Acquire)
+    }
+
+    fn cancel_co(&self) {
+        if let Some(co) = self.co.read() {
+            unsafe { co.cancel() };
+        }
+    }
+
+    pub fn info(&self, stopping: bool) -> ChildInfo {
+        let state = if self.is_timed_out() {
+            ChildState::TimedOut
+        } else if self.state.load(Ordering::Acquire) == CANCELLED {
+            ChildState::Cancelled
+        } else if stopping {
+            ChildState::Stopping
+        } else {
+            ChildState::Running
+        };
+        ChildInfo {
+            id: self.id,
+            name: self.name.clone(),
+            spawned_at: self.spawned_at,
+            state,
+        }
+    }
+}
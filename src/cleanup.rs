@@ -0,0 +1,209 @@
+use crate::Node;
+
+use std::fmt;
+
+/// a guard that runs the cleanup closure when dropped
+///
+/// created by `defer` or `on_cancel`. the cleanup runs in place, unless the
+/// managed sub coroutine is cancelled, then the cleanups of the sub coroutine
+/// run one by one in drop order in a new coroutine after it exits, so they can
+/// still do the blocking io. that cleanup is masked from the cancel of the
+/// manager and is not reported as a sub coroutine, the manager drop waits it
+/// until it's done or runs out of the `Manager::set_cleanup_timeout`
+#[must_use = "the cleanup runs when the guard is dropped"]
+pub struct Cleanup {
+    f: Option<Box<dyn FnOnce() + Send>>,
+    // only run the cleanup when the sub coroutine is cancelled
+    on_cancel: bool,
+}
+
+/// run the closure when the returned guard is dropped
+///
+/// ```rust,no_run
+/// use co_managed::Manager;
+///
+/// let manager = Manager::new();
+/// manager.add(|| {
+///     let _guard = co_managed::defer(|| { /* send the goodbye frame */ });
+///     // serve the connection
+/// });
+/// ```
+pub fn defer<F>(f: F) -> Cleanup
+where
+    F: FnOnce() + Send + 'static,
+{
+    Cleanup {
+        f: Some(Box::new(f)),
+        on_cancel: false,
+    }
+}
+
+/// same as `defer` except that the closure only runs if the sub coroutine
+/// is cancelled by its manager
+pub fn on_cancel<F>(f: F) -> Cleanup
+where
+    F: FnOnce() + Send + 'static,
+{
+    Cleanup {
+        f: Some(Box::new(f)),
+        on_cancel: true,
+    }
+}
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        let Some(f) = self.f.take() else {
+            return;
+        };
+        let current = crate::current();
+        let cancelled = current.as_ref().and_then(|(_, node)| match &**node {
+            Node::Co(child) if child.is_cancelled() => Some(child),
+            _ => None,
+        });
+        match cancelled {
+            // a cancelled coroutine can't block, the manager runs the cleanup instead
+            Some(child) => child.queue_cleanup(f),
+            None if self.on_cancel => {}
+            None => f(),
+        }
+    }
+}
+
+impl fmt::Debug for Cleanup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cleanup")
+            .field("on_cancel", &self.on_cancel)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Manager;
+    use may::coroutine;
+    use may::sync::mpsc::channel;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn cleanup_on_exit() {
+        let manager = Manager::new();
+        let (tx, rx) = channel();
+        let h = manager.spawn(move || {
+            let tx1 = tx.clone();
+            let _a = defer(move || tx1.send("defer").unwrap());
+            let _b = on_cancel(move || tx.send("on_cancel").unwrap());
+        });
+        h.join().unwrap();
+        assert_eq!(rx.recv().unwrap(), "defer");
+        assert!(rx.recv().is_err());
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn cleanup_on_cancel() {
+        let (tx, rx) = channel();
+        let manager = Manager::new();
+        manager.add(move || {
+            let _guard = on_cancel(move || {
+                // the blocking io still works in the cleanup
+                coroutine::sleep(Duration::from_millis(20));
+                tx.send("goodbye").unwrap();
+            });
+            coroutine::sleep(Duration::from_secs(10));
+        });
+        coroutine::sleep(Duration::from_millis(10));
+        assert_eq!(manager.cancel_all(), 1);
+        coroutine::sleep(Duration::from_millis(5));
+        // the cleanup is masked from the cancel and is not reported
+        assert!(manager.is_empty());
+        assert_eq!(manager.children().count(), 0);
+        assert_eq!(manager.cancel_all(), 0);
+        drop(manager);
+        assert_eq!(rx.try_recv().unwrap(), "goodbye");
+    }
+
+    #[test]
+    fn cleanup_drop_order() {
+        let (tx, rx) = channel();
+        let manager = Manager::new();
+        manager.add(move || {
+            let tx1 = tx.clone();
+            let _outer = defer(move || {
+                coroutine::sleep(Duration::from_millis(10));
+                tx1.send("close").unwrap();
+            });
+            let _inner = on_cancel(move || {
+                coroutine::sleep(Duration::from_millis(20));
+                tx.send("flush").unwrap();
+            });
+            coroutine::sleep(Duration::from_secs(10));
+        });
+        coroutine::sleep(Duration::from_millis(10));
+        drop(manager);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), ["flush", "close"]);
+    }
+
+    #[test]
+    fn cleanup_not_reported() {
+        use std::sync::atomic::AtomicUsize;
+
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = channel();
+        let manager = Manager::new();
+        let events = manager.exit_events();
+        let n = spawned.clone();
+        manager.on_spawn(move |_| {
+            n.fetch_add(1, Ordering::SeqCst);
+        });
+        for _ in 0..2 {
+            let tx = tx.clone();
+            manager.add(move || {
+                let guards: Vec<_> = (0..3)
+                    .map(|i| {
+                        let tx = tx.clone();
+                        defer(move || tx.send(i).unwrap())
+                    })
+                    .collect();
+                coroutine::sleep(Duration::from_millis(20));
+                drop(guards);
+            });
+        }
+        coroutine::sleep(Duration::from_millis(10));
+        manager.children().take(1).for_each(|info| {
+            manager.cancel(info.id);
+        });
+        assert!(manager.wait_idle(Duration::from_secs(1)));
+        drop(tx);
+        assert_eq!(rx.iter().count(), 6);
+
+        let stats = manager.stats();
+        assert_eq!(stats.spawned, 2);
+        assert_eq!(stats.completed, 1);
+        assert_eq!(stats.cancelled, 1);
+        assert_eq!(spawned.load(Ordering::SeqCst), 2);
+        assert_eq!(events.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn cleanup_timeout() {
+        let done = Arc::new(AtomicBool::new(false));
+        let flag = done.clone();
+        let start = Instant::now();
+        let manager = Manager::new();
+        manager.set_cleanup_timeout(Duration::from_millis(20));
+        manager.add(move || {
+            let _guard = defer(move || {
+                coroutine::sleep(Duration::from_secs(10));
+                flag.store(true, Ordering::SeqCst);
+            });
+            coroutine::sleep(Duration::from_secs(10));
+        });
+        coroutine::sleep(Duration::from_millis(10));
+        drop(manager);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!done.load(Ordering::SeqCst));
+    }
+}
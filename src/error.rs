@@ -0,0 +1,27 @@
+use std::fmt;
+
+/// the error type of the managed sub coroutines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// the manager already reached its capacity limit
+    Full,
+    /// the supervisor restarted its children too many times
+    TooManyRestarts,
+    /// the manager is dropped or started to shut down
+    Closed,
+    /// the sub coroutine already exited, or doesn't belong to the manager
+    NotFound,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Full => f.pad("manager is full"),
+            Error::TooManyRestarts => f.pad("too many restarts"),
+            Error::Closed => f.pad("manager is closed"),
+            Error::NotFound => f.pad("sub coroutine not found"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
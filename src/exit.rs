@@ -0,0 +1,77 @@
+use crate::panic::{self, PanicPayload};
+
+use std::any::Any;
+use std::fmt;
+
+/// why a managed sub coroutine exited
+pub enum ExitReason {
+    /// the sub coroutine returned normally
+    Completed,
+    /// the sub coroutine panicked, with the payload re-raised by the sub coroutine
+    Panicked(PanicPayload),
+    /// the sub coroutine is cancelled by its manager or handle
+    Cancelled,
+    /// the sub coroutine ran out of its time budget and is cancelled
+    TimedOut,
+}
+
+impl ExitReason {
+    // derive the reason from the payload of the failed sub coroutine
+    pub(crate) fn from_panic(payload: PanicPayload, timed_out: bool) -> Self {
+        if !panic::is_cancel(&*payload) {
+            ExitReason::Panicked(payload)
+        } else if timed_out {
+            ExitReason::TimedOut
+        } else {
+            ExitReason::Cancelled
+        }
+    }
+
+    // same as `from_panic` except that the payload is borrowed, only the message is kept
+    pub(crate) fn of_panic(payload: &(dyn Any + Send), timed_out: bool) -> Self {
+        if !panic::is_cancel(payload) {
+            ExitReason::Panicked(Box::new(panic::panic_message(payload)))
+        } else if timed_out {
+            ExitReason::TimedOut
+        } else {
+            ExitReason::Cancelled
+        }
+    }
+
+    // the panic payload can't be cloned, the copy only keeps the message
+    pub(crate) fn copy(&self) -> Self {
+        match self {
+            ExitReason::Completed => ExitReason::Completed,
+            ExitReason::Panicked(payload) => {
+                ExitReason::Panicked(Box::new(panic::panic_message(&**payload)))
+            }
+            ExitReason::Cancelled => ExitReason::Cancelled,
+            ExitReason::TimedOut => ExitReason::TimedOut,
+        }
+    }
+}
+
+impl fmt::Debug for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitReason::Completed => f.pad("Completed"),
+            ExitReason::Panicked(payload) => f
+                .debug_tuple("Panicked")
+                .field(&panic::panic_message(&**payload))
+                .finish(),
+            ExitReason::Cancelled => f.pad("Cancelled"),
+            ExitReason::TimedOut => f.pad("TimedOut"),
+        }
+    }
+}
+
+/// the event sent by `Manager::exit_events` when a sub coroutine exits
+#[derive(Debug)]
+pub struct ExitEvent {
+    /// the unique id of the sub coroutine
+    pub id: usize,
+    /// the name of the sub coroutine
+    pub name: Option<String>,
+    /// why the sub coroutine exited
+    pub reason: ExitReason,
+}
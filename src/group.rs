@@ -0,0 +1,173 @@
+use crate::{panic, ManagedHandle, Manager, PanicPolicy};
+
+use std::fmt;
+use std::panic::resume_unwind;
+use std::sync::{Arc, Mutex};
+
+/// a group of tasks that fail together
+///
+/// the tasks run in a sub manager, the first task that returns `Err` cancels
+/// all the other tasks in the group. a panic in a task also cancels the others
+/// and is re-raised by `wait`. dropping the group without `wait` cancels the
+/// running tasks
+///
+/// ```rust,no_run
+/// use co_managed::Manager;
+///
+/// let manager = Manager::new();
+/// let mut group = manager.group();
+/// for i in 0..10 {
+///     group.spawn(move || if i < 5 { Ok(i) } else { Err(i) });
+/// }
+/// assert!(group.wait().is_err());
+/// ```
+pub struct TaskGroup<T, E> {
+    manager: Manager,
+    handles: Vec<ManagedHandle<Option<T>>>,
+    error: Arc<Mutex<Option<E>>>,
+}
+
+impl<T, E> TaskGroup<T, E> {
+    pub(crate) fn new(manager: Manager) -> Self {
+        manager.set_panic_policy(PanicPolicy::Escalate);
+        TaskGroup {
+            manager,
+            handles: Vec::new(),
+            error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// return the number of the tasks spawned in the group
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// return true if no task is spawned in the group
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
+
+impl<T, E> TaskGroup<T, E>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    /// spawn a task in the group
+    pub fn spawn<F>(&mut self, f: F)
+    where
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+    {
+        let error = self.error.clone();
+        let handle = self.manager.spawn(move || match f() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                let mut first = error.lock().unwrap();
+                if first.is_none() {
+                    *first = Some(e);
+                    drop(first);
+                    crate::cancel_siblings();
+                }
+                None
+            }
+        });
+        self.handles.push(handle);
+    }
+
+    /// wait all the tasks to exit
+    ///
+    /// return the first error, or the values in the spawn order. the panic of
+    /// a task is re-raised here, and so is the cancel panic if the group is
+    /// cancelled by its parent manager
+    pub fn wait(self) -> Result<Vec<T>, E> {
+        let mut values = Vec::with_capacity(self.handles.len());
+        let mut cancelled = None;
+        for handle in self.handles {
+            match handle.join() {
+                Ok(Some(v)) => values.push(v),
+                Ok(None) => {}
+                Err(payload) if panic::is_cancel(&*payload) => cancelled = Some(payload),
+                // the original panic is escalated to the manager
+                Err(_) => {}
+            }
+        }
+        self.manager.inner.check_escalated();
+        if let Some(e) = self.error.lock().unwrap().take() {
+            return Err(e);
+        }
+        if let Some(payload) = cancelled {
+            resume_unwind(payload);
+        }
+        Ok(values)
+    }
+}
+
+impl<T, E> fmt::Debug for TaskGroup<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TaskGroup")
+            .field("tasks", &self.handles.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use may::coroutine;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn all_ok() {
+        let manager = Manager::new();
+        let mut group = manager.group::<_, ()>();
+        for i in 0..10 {
+            group.spawn(move || {
+                coroutine::sleep(Duration::from_millis(10 - i));
+                Ok(i)
+            });
+        }
+        assert_eq!(group.wait(), Ok((0..10).collect()));
+    }
+
+    #[test]
+    fn first_error_cancels_siblings() {
+        let manager = Manager::new();
+        let finished = Arc::new(AtomicUsize::new(0));
+        let mut group = manager.group::<(), _>();
+        for _ in 0..10 {
+            let finished = finished.clone();
+            group.spawn(move || {
+                coroutine::sleep(Duration::from_secs(10));
+                finished.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            });
+        }
+        group.spawn(|| {
+            coroutine::sleep(Duration::from_millis(10));
+            Err("first")
+        });
+        group.spawn(|| {
+            coroutine::sleep(Duration::from_millis(50));
+            Err("second")
+        });
+
+        let start = Instant::now();
+        assert_eq!(group.wait(), Err("first"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "task panic")]
+    fn panic_cancels_siblings() {
+        let manager = Manager::new();
+        let mut group = manager.group::<(), ()>();
+        group.spawn(|| {
+            coroutine::sleep(Duration::from_secs(10));
+            Ok(())
+        });
+        group.spawn(|| panic!("task panic"));
+        let _ = group.wait();
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{CoNode, ExitReason, Node};
+use may::coroutine::{self, Coroutine};
+
+use std::fmt;
+use std::thread;
+
+/// a handle to a managed sub coroutine
+///
+/// the handle can be used to wait for the result of the sub coroutine,
+/// the sub coroutine is still owned by its `Manager`
+pub struct ManagedHandle<T> {
+    co: coroutine::JoinHandle<T>,
+    node: CoNode,
+}
+
+impl<T> ManagedHandle<T> {
+    pub(crate) fn new(co: coroutine::JoinHandle<T>, node: CoNode) -> Self {
+        ManagedHandle { co, node }
+    }
+
+    pub(crate) fn node(&self) -> &CoNode {
+        &self.node
+    }
+
+    /// the unique id of the sub coroutine, the same as `ChildInfo::id`
+    pub fn id(&self) -> usize {
+        match &*self.node {
+            Node::Co(child) => child.id(),
+            Node::Manager(_) => 0,
+        }
+    }
+
+    /// get the underlying coroutine
+    pub fn coroutine(&self) -> &Coroutine {
+        self.co.coroutine()
+    }
+
+    /// return true if the sub coroutine is finished
+    pub fn is_done(&self) -> bool {
+        self.co.is_done()
+    }
+
+    /// return true if the sub coroutine is cancelled because it ran out of
+    /// its time budget
+    pub fn is_timed_out(&self) -> bool {
+        match &*self.node {
+            Node::Co(child) => child.is_timed_out(),
+            Node::Manager(_) => false,
+        }
+    }
+
+    /// block until the sub coroutine is finished
+    pub fn wait(&self) {
+        self.co.wait()
+    }
+
+    /// cancel the sub coroutine
+    ///
+    /// this is the same cancel that the `Manager` applies when dropped
+    pub fn cancel(&self) {
+        if let Node::Co(child) = &*self.node {
+            // the detached sub coroutine is cancelled directly
+            if !child.cancel() && child.is_detached() {
+                unsafe { self.co.coroutine().cancel() };
+            }
+        }
+    }
+
+    /// take the sub coroutine out of its `Manager` and return the raw handle
+    ///
+    /// the detached sub coroutine is no longer cancelled when the manager is
+    /// dropped, and its exit is not counted or reported by the manager. it's
+    /// still cancelled if the manager already cancelled it before the detach.
+    /// the sub coroutine of a `scope` is never detached
+    pub fn detach(self) -> coroutine::JoinHandle<T> {
+        if let Node::Co(child) = &*self.node {
+            child.detach();
+        }
+        self.co
+    }
+
+    /// wait the sub coroutine finished and return its result
+    ///
+    /// return `Err` if the sub coroutine panicked or was cancelled
+    pub fn join(self) -> thread::Result<T> {
+        self.co.join()
+    }
+
+    /// wait the sub coroutine finished and return why it exited
+    ///
+    /// the result of the sub coroutine is dropped
+    pub fn exit_reason(self) -> ExitReason {
+        self.co.wait();
+        let timed_out = self.is_timed_out();
+        match self.co.join() {
+            Ok(_) => ExitReason::Completed,
+            Err(payload) => ExitReason::from_panic(payload, timed_out),
+        }
+    }
+}
+
+impl<T> fmt::Debug for ManagedHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("ManagedHandle { .. }")
+    }
+}
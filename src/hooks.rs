@@ -0,0 +1,17 @@
+use crate::{ChildInfo, ExitReason};
+
+use std::sync::Arc;
+
+type ChildHook = Arc<dyn Fn(&ChildInfo) + Send + Sync>;
+type ExitHook = Arc<dyn Fn(&ChildInfo, &ExitReason) + Send + Sync>;
+type DropHook = Arc<dyn Fn() + Send + Sync>;
+
+// the lifecycle callbacks registered on a manager
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub on_spawn: Option<ChildHook>,
+    pub on_exit: Option<ExitHook>,
+    pub on_cancel: Option<ChildHook>,
+    pub on_drop_start: Option<DropHook>,
+    pub on_drop_done: Option<DropHook>,
+}
@@ -0,0 +1,142 @@
+use may::coroutine::{trigger_cancel_panic, ParkError};
+use may::sync::Blocker;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// count the running sub coroutines and wake up the waiters when it drops to zero
+#[derive(Default)]
+pub(crate) struct Idle {
+    cnt: AtomicUsize,
+    waiters: Mutex<Vec<Arc<Blocker>>>,
+    // notified with the waiters lock held, for the waiters that block the thread
+    zero: Condvar,
+}
+
+impl Idle {
+    pub fn count(&self) -> usize {
+        self.cnt.load(Ordering::Acquire)
+    }
+
+    // return the count after increased
+    pub fn inc(&self) -> usize {
+        self.cnt.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    pub fn dec(&self) {
+        if self.cnt.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let mut waiters = self.waiters.lock().unwrap();
+            std::mem::take(&mut *waiters)
+                .iter()
+                .for_each(|w| w.unpark());
+            self.zero.notify_all();
+        }
+    }
+
+    // the waiter gives up, the blocker is only drained when the count drops to zero
+    fn remove(&self, blocker: &Arc<Blocker>) {
+        self.waiters
+            .lock()
+            .unwrap()
+            .retain(|w| !Arc::ptr_eq(w, blocker));
+    }
+
+    /// block until the count drops to zero, return false if timeout
+    ///
+    /// if the waiting coroutine is cancelled, it would trigger the cancel panic
+    /// unless it's already unwinding, then just return false since it can't block
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        self.wait_until(timeout).unwrap_or_else(|| {
+            if !thread::panicking() {
+                trigger_cancel_panic();
+            }
+            false
+        })
+    }
+
+    /// same as `wait` but never trigger the cancel panic, return false if
+    /// the waiting coroutine is cancelled
+    pub fn wait_uncancelled(&self, timeout: Option<Duration>) -> bool {
+        self.wait_until(timeout).unwrap_or(false)
+    }
+
+    // return None if the waiting coroutine is cancelled
+    fn wait_until(&self, timeout: Option<Duration>) -> Option<bool> {
+        let deadline = timeout.map(|dur| Instant::now() + dur);
+        loop {
+            if self.count() == 0 {
+                return Some(true);
+            }
+            let dur = match deadline {
+                None => None,
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(dur) => Some(dur),
+                    None => return Some(false),
+                },
+            };
+            // we handle the cancel by ourself
+            let blocker = Arc::new(Blocker::new(true));
+            self.waiters.lock().unwrap().push(blocker.clone());
+            // re-check the count after register the blocker
+            if self.count() == 0 {
+                return Some(true);
+            }
+
+            match blocker.park(dur) {
+                Ok(()) => {}
+                Err(ParkError::Timeout) => {
+                    self.remove(&blocker);
+                    return Some(self.count() == 0);
+                }
+                Err(ParkError::Canceled) => {
+                    self.remove(&blocker);
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// block until the count drops to zero, even if the waiting coroutine is cancelled
+    ///
+    /// a cancelled coroutine can't park, so it blocks the worker thread instead
+    /// until the last sub coroutine exits on the other workers
+    pub fn wait_all(&self) {
+        loop {
+            if self.count() == 0 {
+                return;
+            }
+            let blocker = Arc::new(Blocker::new(true));
+            self.waiters.lock().unwrap().push(blocker.clone());
+            if self.count() == 0 {
+                return;
+            }
+            if let Err(ParkError::Canceled) = blocker.park(None) {
+                self.remove(&blocker);
+                break;
+            }
+        }
+        let mut waiters = self.waiters.lock().unwrap();
+        while self.count() != 0 {
+            waiters = self.zero.wait(waiters).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_waiters_removed() {
+        let idle = Idle::default();
+        idle.inc();
+        for _ in 0..10 {
+            assert!(!idle.wait(Some(Duration::from_millis(1))));
+        }
+        assert!(idle.waiters.lock().unwrap().is_empty());
+        idle.dec();
+        assert!(idle.wait(None));
+    }
+}
@@ -6,105 +6,1084 @@
 #![doc = include_str!("../README.md")]
 #[macro_use]
 extern crate may;
+use child::Child;
+use hooks::Hooks;
+use idle::Idle;
 use may::coroutine;
+use may::sync::mpsc::{channel, Receiver, Sender};
+use may::sync::Semphore;
 use rcu_cell::RcuCell;
-use rcu_list::d_list::{Entry, LinkedList};
+use rcu_list::d_list::{LinkedList, StaticEntry};
+use stats::Stats;
 
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::io;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use timer::Timer;
 
-type CoNode = Arc<RcuCell<coroutine::JoinHandle<()>>>;
-type CoList = Arc<LinkedList<CoNode>>;
+mod builder;
+mod child;
+mod cleanup;
+mod error;
+mod exit;
+mod group;
+mod handle;
+mod hooks;
+mod idle;
+mod panic;
+mod scope;
+mod spawner;
+mod stats;
+mod supervisor;
+mod timer;
+mod token;
+
+pub use builder::Builder;
+pub use child::{ChildInfo, ChildState};
+pub use cleanup::{defer, on_cancel, Cleanup};
+pub use error::Error;
+pub use exit::{ExitEvent, ExitReason};
+pub use group::TaskGroup;
+pub use handle::ManagedHandle;
+pub use panic::{panic_message, PanicPayload, PanicPolicy};
+pub use scope::{scope, Scope};
+pub use spawner::Spawner;
+pub use stats::{LifetimeHistogram, ManagerStats};
+pub use supervisor::{Strategy, Supervisor};
+pub use token::CancelToken;
+
+// the entry in the manager list, either a sub coroutine or a sub manager
+enum Node {
+    Co(Child),
+    Manager(Weak<Inner>),
+}
+
+type CoNode = Arc<Node>;
+
+// the node of the current sub coroutine
+coroutine_local!(static CURRENT: RefCell<Option<CoNode>> = RefCell::new(None));
+
+// the manager and the node of the current sub coroutine
+fn current() -> Option<(Arc<Inner>, CoNode)> {
+    let node = CURRENT.with(|cur| cur.borrow().clone())?;
+    let Node::Co(child) = &*node else {
+        return None;
+    };
+    Some((child.manager()?, node))
+}
+
+/// return true if the manager of the current sub coroutine requested it to stop
+///
+/// this is the cooperative stop signal sent by `Manager::shutdown`, the sub
+/// coroutine should poll it and exit on its own before the grace period ends
+pub fn stop_requested() -> bool {
+    current().is_some_and(|(inner, _)| inner.token.is_cancelled())
+}
+
+/// return the span that is current when the current sub coroutine is spawned
+///
+/// the span is not entered while the sub coroutine runs, since it would stay
+/// entered on the worker thread when the sub coroutine yields. so the logs in
+/// the sub coroutine don't carry the span unless they are wrapped by
+/// `current_span().in_scope`, around the code that doesn't yield
+#[cfg(feature = "tracing")]
+pub fn current_span() -> tracing::Span {
+    CURRENT.with(|cur| match cur.borrow().as_deref() {
+        Some(Node::Co(child)) => child.span().clone(),
+        _ => tracing::Span::none(),
+    })
+}
+
+// cancel the siblings of the current sub coroutine in its manager
+fn cancel_siblings() {
+    if let Some((inner, node)) = current() {
+        inner.token.cancel();
+        inner.cancel_all(Some(&node));
+    }
+}
+
+/// how a `Manager` stops its sub coroutines when dropped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropMode {
+    /// cancel all the sub coroutines immediately
+    #[default]
+    Cancel,
+    /// request the sub coroutines to stop and cancel the ones
+    /// that are still running after the grace period
+    Graceful(Duration),
+}
+
+/// a snapshot of a manager and its sub managers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManagerTree {
+    /// number of the sub coroutines that directly belong to the manager
+    pub coroutines: usize,
+    /// the sub managers created by `Manager::child`
+    pub children: Vec<ManagerTree>,
+}
+
+#[derive(Default)]
+struct Inner {
+    co_list: LinkedList<CoNode>,
+    panic_policy: RcuCell<PanicPolicy>,
+    panics: Mutex<Vec<PanicPayload>>,
+    drop_mode: RcuCell<DropMode>,
+    // how long the cleanup of a cancelled sub coroutine may take
+    cleanup_timeout: RcuCell<Duration>,
+    // cancelled when the manager start to stop
+    token: CancelToken,
+    // the sub coroutines borrow from a `scope`
+    scoped: bool,
+    // no more sub coroutines are accepted once the manager start to stop
+    closed: AtomicBool,
+    // the available slots when the manager has a capacity limit
+    limit: Option<Semphore>,
+    // the number of running sub coroutines, including the cleanups
+    alive: Idle,
+    // the number of running cleanups, they are not reported as sub coroutines
+    cleanups: AtomicUsize,
+    // the deadline of all the sub coroutines
+    deadline: Option<Instant>,
+    timer: Arc<Timer>,
+    // the subscribers of the exit events
+    events: Mutex<Vec<Sender<ExitEvent>>>,
+    hooks: RcuCell<Hooks>,
+    stats: Stats,
+}
+
+impl Inner {
+    // re-raise the escalated panic in the parent
+    fn check_escalated(&self) {
+        if let Some(PanicPolicy::Escalate) = self.panic_policy.read().as_deref() {
+            let payload = std::mem::take(&mut *self.panics.lock().unwrap())
+                .into_iter()
+                .next();
+            if let (Some(payload), false) = (payload, thread::panicking()) {
+                resume_unwind(payload);
+            }
+        }
+    }
+
+    // stop all the sub coroutines, give them the grace period to exit on their own
+    fn stop(&self, grace: Option<Duration>) {
+        self.close();
+        self.token.cancel();
+        if let Some(grace) = grace {
+            // the sub managers share the grace period. if the current coroutine
+            // is cancelled the grace period is over, the drop must still cancel
+            // the sub coroutines and the cancel is seen at its next yield
+            self.wait_tree(Instant::now() + grace);
+        }
+        // cancel all the sub coroutines
+        self.cancel_all(None);
+        // the sub managers are owned by others, stop them and unlink them here
+        self.co_list.iter().for_each(|node| {
+            if let Node::Manager(sub) = &**node {
+                if let Some(sub) = sub.upgrade() {
+                    sub.stop(None);
+                }
+                node.remove();
+            }
+        });
+        // the last SubCo drop would wake us up. if the current coroutine is
+        // cancelled it can't block, the cancelled sub coroutines would then
+        // exit on their own
+        self.alive.wait_uncancelled(None);
+    }
+
+    // close the manager and its sub managers, wait until all their sub
+    // coroutines exit, return false if the deadline is reached
+    fn wait_tree(&self, deadline: Instant) -> bool {
+        self.close();
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if !self.alive.wait_uncancelled(Some(timeout)) {
+            return false;
+        }
+        self.co_list.iter().all(|node| match &**node {
+            Node::Manager(sub) => sub.upgrade().is_none_or(|sub| sub.wait_tree(deadline)),
+            Node::Co(_) => true,
+        })
+    }
+
+    // pairs with the fence in `register`, either the stop sees the new entry
+    // or the spawner sees the closed state
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    // put the node in the list, return false if the manager is closed
+    // that the node may be missed by the stop
+    fn register(&self, node: CoNode) -> (StaticEntry<CoNode>, bool) {
+        let entry = self.co_list.push_front(node).into_static();
+        fence(Ordering::SeqCst);
+        (entry, !self.is_closed())
+    }
+
+    // move the sub coroutine from its current manager into this one
+    fn adopt(self: &Arc<Self>, node: &CoNode) -> Result<(), Error> {
+        let Node::Co(child) = &**node else {
+            return Err(Error::NotFound);
+        };
+        // the scope must join the sub coroutines that borrow from it
+        if child.is_scoped() || child.is_cleanup() {
+            return Err(Error::NotFound);
+        }
+        let mut owner = child.owner();
+        // the sub coroutine already exited or is detached
+        let Some(old) = owner.as_ref() else {
+            return Err(Error::NotFound);
+        };
+        if Arc::ptr_eq(&old.inner, self) {
+            return Ok(());
+        }
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+        if let Some(limit) = &self.limit {
+            if !limit.try_wait() {
+                return Err(Error::Full);
+            }
+        }
+        let alive = self.alive.inc();
+        let (entry, open) = self.register(node.clone());
+        if !open {
+            Owner {
+                inner: self.clone(),
+                entry,
+            }
+            .release();
+            return Err(Error::Closed);
+        }
+        self.stats.spawn(alive);
+        let old = owner.replace(Owner {
+            inner: self.clone(),
+            entry,
+        });
+        drop(owner);
+        if let Some(old) = old {
+            old.inner.stats.transfer();
+            old.release();
+        }
+        if let Some(deadline) = child.deadline() {
+            self.timer.add(deadline, node);
+        }
+        Ok(())
+    }
+
+    // cancel all the sub coroutines except the `skip` one
+    // the cancel is cascaded to all the sub managers
+    fn cancel_all(&self, skip: Option<&CoNode>) {
+        self.co_list.iter().for_each(|node| {
+            if skip.is_some_and(|skip| Arc::ptr_eq(skip, &node)) {
+                return;
+            }
+            match &**node {
+                Node::Co(child) => {
+                    child.cancel();
+                }
+                Node::Manager(sub) => {
+                    if let Some(sub) = sub.upgrade() {
+                        sub.token.cancel();
+                        sub.cancel_all(None);
+                    }
+                }
+            }
+        });
+    }
+
+    fn tree(&self) -> ManagerTree {
+        let mut tree = ManagerTree::default();
+        self.co_list.iter().for_each(|node| match &**node {
+            Node::Co(child) if child.is_cleanup() => {}
+            Node::Co(_) => tree.coroutines += 1,
+            Node::Manager(sub) => {
+                if let Some(sub) = sub.upgrade() {
+                    tree.children.push(sub.tree());
+                }
+            }
+        });
+        tree
+    }
+
+    // apply the panic policy, return the payload that the sub coroutine re-raise
+    fn handle_panic(&self, node: &CoNode, payload: PanicPayload) -> PanicPayload {
+        if panic::is_cancel(&*payload) {
+            return payload;
+        }
+
+        match self.panic_policy.read().as_deref() {
+            None | Some(PanicPolicy::Ignore) => payload,
+            Some(PanicPolicy::Record) => self.record_panic(payload),
+            Some(PanicPolicy::Escalate) => {
+                let payload = self.record_panic(payload);
+                self.cancel_all(Some(node));
+                payload
+            }
+            Some(PanicPolicy::Hook(hook)) => {
+                hook(&*payload);
+                payload
+            }
+        }
+    }
+
+    fn child_info(&self, node: &CoNode) -> Option<ChildInfo> {
+        match &**node {
+            Node::Co(child) => Some(child.info(self.token.is_cancelled())),
+            Node::Manager(_) => None,
+        }
+    }
+
+    // call the exit hook and send the exit event to the subscribers
+    fn notify_exit(&self, node: &CoNode, reason: &ExitReason) {
+        let Node::Co(child) = &**node else {
+            return;
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            parent: child.span(),
+            id = child.id(),
+            name = child.name().unwrap_or_default(),
+            reason = ?reason,
+            "managed coroutine exited"
+        );
+        if let Some(on_exit) = self.hooks.read().and_then(|h| h.on_exit.clone()) {
+            if let Some(info) = self.child_info(node) {
+                on_exit(&info, reason);
+            }
+        }
+        let mut events = self.events.lock().unwrap();
+        events.retain(|tx| {
+            tx.send(ExitEvent {
+                id: child.id(),
+                name: child.name().map(String::from),
+                reason: reason.copy(),
+            })
+            .is_ok()
+        });
+    }
+
+    // keep the payload and return a copy of the message for the sub coroutine
+    fn record_panic(&self, payload: PanicPayload) -> PanicPayload {
+        let msg = panic::panic_message(&*payload);
+        self.panics.lock().unwrap().push(payload);
+        Box::new(msg)
+    }
+
+    fn cleanups(&self) -> usize {
+        self.cleanups.load(Ordering::Acquire)
+    }
+
+    // block until a slot of the capacity limit is available
+    fn acquire_slot(&self) {
+        if let Some(limit) = &self.limit {
+            limit.wait();
+        }
+    }
+
+    // the slot of the capacity limit is already acquired
+    fn spawn_impl<F, T>(
+        self: &Arc<Self>,
+        name: Option<String>,
+        stack_size: Option<usize>,
+        deadline: Option<Instant>,
+        f: F,
+    ) -> io::Result<ManagedHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let child = Child::new(name, deadline, self.scoped);
+        self.spawn_child(child, stack_size, f)
+    }
+
+    // run the cleanup in a sub coroutine that the manager drop would wait,
+    // it's spawned even if the manager is closed
+    fn spawn_cleanup<F>(self: &Arc<Self>, f: F) -> io::Result<ManagedHandle<()>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let timeout = self.cleanup_timeout.read();
+        let deadline = timeout.map(|timeout| Instant::now() + *timeout);
+        self.spawn_child(Child::cleanup(deadline), None, f)
+    }
+
+    fn spawn_child<F, T>(
+        self: &Arc<Self>,
+        child: Child,
+        stack_size: Option<usize>,
+        f: F,
+    ) -> io::Result<ManagedHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut builder = coroutine::Builder::new();
+        if let Some(name) = child.name() {
+            builder = builder.name(name.to_string());
+        }
+        if let Some(size) = stack_size {
+            builder = builder.stack_size(size);
+        }
+
+        let slot = Arc::new(Node::Co(child));
+        let Node::Co(child) = &*slot else {
+            unreachable!()
+        };
+
+        // register the sub coroutine before it's running, so that the
+        // manager can always see and cancel it
+        let alive = self.alive.inc();
+        if child.is_cleanup() {
+            self.cleanups.fetch_add(1, Ordering::AcqRel);
+        } else {
+            self.stats.spawn(alive.saturating_sub(self.cleanups()));
+        }
+        let (entry, open) = self.register(slot.clone());
+        let open = open || child.is_cleanup();
+        *child.owner() = Some(Owner {
+            inner: self.clone(),
+            entry,
+        });
+        // the manager started to stop, the sub coroutine is cancelled before it runs.
+        // a coroutine only sees the cancel at a yield point, so the body checks it too
+        if !open {
+            child.cancel();
+        }
+        let sub_co = SubCo {
+            node: slot.clone(),
+            reason: None,
+        };
+        if let Some(deadline) = child.deadline() {
+            self.timer.add(deadline, &slot);
+        }
+        if !child.is_cleanup() {
+            if let Some(on_spawn) = self.hooks.read().and_then(|h| h.on_spawn.clone()) {
+                if let Some(info) = self.child_info(&slot) {
+                    on_spawn(&info);
+                }
+            }
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                parent: child.span(),
+                id = child.id(),
+                name = child.name().unwrap_or_default(),
+                "managed coroutine spawned"
+            );
+        }
+
+        let co = unsafe {
+            builder.spawn(move || {
+                // the SubCo drop would unregister the sub coroutine
+                let mut sub_co = sub_co;
+                CURRENT.with(|c| *c.borrow_mut() = Some(sub_co.node.clone()));
+                let f = move || {
+                    if !open {
+                        coroutine::trigger_cancel_panic();
+                    }
+                    f()
+                };
+                match catch_unwind(AssertUnwindSafe(f)) {
+                    Ok(ret) => {
+                        sub_co.reason = Some(ExitReason::Completed);
+                        ret
+                    }
+                    Err(payload) => {
+                        // the sub coroutine may be moved to another manager
+                        let (payload, timed_out) = match current() {
+                            Some((inner, node)) => {
+                                let payload = inner.handle_panic(&node, payload);
+                                let timed_out = match &*node {
+                                    Node::Co(child) => child.is_timed_out(),
+                                    Node::Manager(_) => false,
+                                };
+                                (payload, timed_out)
+                            }
+                            // a detached sub coroutine is not managed anymore
+                            None => (payload, false),
+                        };
+                        sub_co.reason = Some(ExitReason::of_panic(&*payload, timed_out));
+                        resume_unwind(payload)
+                    }
+                }
+            })
+        }?;
+        // setup the coroutine handle
+        child.set_coroutine(co.coroutine().clone());
+        Ok(ManagedHandle::new(co, slot))
+    }
+}
 
 #[derive(Default)]
 pub struct Manager {
-    co_list: CoList,
+    inner: Arc<Inner>,
+    // the entry in the parent manager list
+    link: Option<StaticEntry<CoNode>>,
 }
 
 impl Manager {
     pub fn new() -> Self {
         Manager {
-            co_list: Arc::new(Default::default()),
+            inner: Arc::new(Default::default()),
+            link: None,
+        }
+    }
+
+    /// create a manager that runs at most `n` sub coroutines at the same time
+    ///
+    /// `add` would block the caller until a running sub coroutine exits,
+    /// `try_add` would return `Error::Full` instead
+    pub fn with_capacity_limit(n: usize) -> Self {
+        Manager {
+            inner: Arc::new(Inner {
+                limit: Some(Semphore::new(n)),
+                ..Default::default()
+            }),
+            link: None,
+        }
+    }
+
+    /// create a manager that cancels all its sub coroutines at the deadline
+    ///
+    /// the sub coroutines that are spawned after the deadline are cancelled
+    /// immediately, their handles report that they timed out
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Manager {
+            inner: Arc::new(Inner {
+                deadline: Some(deadline),
+                ..Default::default()
+            }),
+            link: None,
+        }
+    }
+
+    // the manager of a `scope`, its sub coroutines can't be detached or moved
+    pub(crate) fn scoped() -> Self {
+        Manager {
+            inner: Arc::new(Inner {
+                scoped: true,
+                ..Default::default()
+            }),
+            link: None,
+        }
+    }
+
+    /// create a sub manager that is registered in this manager
+    ///
+    /// when this manager is dropped the sub manager is stopped together
+    /// with all its sub coroutines, no matter who owns the sub manager.
+    /// the sub manager inherits the deadline of this manager
+    pub fn child(&self) -> Manager {
+        let inner = Arc::new(Inner {
+            token: self.inner.token.child_token(),
+            deadline: self.inner.deadline,
+            ..Default::default()
+        });
+        let node = Arc::new(Node::Manager(Arc::downgrade(&inner)));
+        let (link, open) = self.inner.register(node);
+        // the parent may already be stopped
+        if !open {
+            inner.stop(None);
+        }
+        Manager {
+            inner,
+            link: Some(link),
+        }
+    }
+
+    /// return the number of the running sub coroutines
+    ///
+    /// the sub managers and the running cleanups are not counted
+    pub fn len(&self) -> usize {
+        self.inner
+            .alive
+            .count()
+            .saturating_sub(self.inner.cleanups())
+    }
+
+    /// return true if there is no running sub coroutines
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// block until all the running sub coroutines exit, without cancelling them
+    ///
+    /// the sub coroutines of the sub managers are not waited. if the panic policy
+    /// is `PanicPolicy::Escalate`, the escalated panic is re-raised here
+    pub fn join_all(&self) {
+        self.inner.alive.wait(None);
+        self.inner.check_escalated();
+    }
+
+    /// block until all the running sub coroutines exit or the timeout expires,
+    /// without cancelling them
+    ///
+    /// return false if timeout
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        self.inner.alive.wait(Some(timeout))
+    }
+
+    /// iterate the information of the running sub coroutines
+    pub fn children(&self) -> impl Iterator<Item = ChildInfo> + '_ {
+        let stopping = self.inner.token.is_cancelled();
+        self.inner
+            .co_list
+            .iter()
+            .filter_map(move |node| match &**node {
+                Node::Co(child) if !child.is_cleanup() => Some(child.info(stopping)),
+                _ => None,
+            })
+    }
+
+    /// create a task group in a sub manager of this manager
+    ///
+    /// the first task that returns `Err` cancels all the other tasks in the group
+    pub fn group<T, E>(&self) -> TaskGroup<T, E> {
+        TaskGroup::new(self.child())
+    }
+
+    /// cancel the running sub coroutine with the id, the manager is still usable
+    ///
+    /// return false if there is no such sub coroutine
+    pub fn cancel(&self, id: usize) -> bool {
+        for node in self.inner.co_list.iter() {
+            if let Node::Co(child) = &**node {
+                if child.id() == id {
+                    return child.cancel();
+                }
+            }
         }
+        false
+    }
+
+    /// take the running sub coroutine with the id out of the manager
+    ///
+    /// it's no longer cancelled when the manager is dropped, its `ManagedHandle`
+    /// can still be used to wait for it. return false if there is no such sub
+    /// coroutine, it's already cancelled or it's spawned by a `scope`
+    pub fn detach(&self, id: usize) -> bool {
+        for node in self.inner.co_list.iter() {
+            if let Node::Co(child) = &**node {
+                if child.id() == id {
+                    return child.detach();
+                }
+            }
+        }
+        false
+    }
+
+    /// move the sub coroutine of the handle from its manager into this one
+    ///
+    /// the sub coroutine is then cancelled with this manager and its exit is
+    /// reported here. return `Error::NotFound` if it already exited, is detached
+    /// or is spawned by a `scope`, `Error::Full` or `Error::Closed` if this
+    /// manager can't take it
+    pub fn adopt<T>(&self, handle: &ManagedHandle<T>) -> Result<(), Error> {
+        self.inner.adopt(handle.node())
+    }
+
+    /// move the running sub coroutine with the id into the other manager
+    ///
+    /// see `adopt` for the errors
+    pub fn transfer(&self, id: usize, other: &Manager) -> Result<(), Error> {
+        for node in self.inner.co_list.iter() {
+            if let Node::Co(child) = &**node {
+                if child.id() == id {
+                    return other.inner.adopt(&node);
+                }
+            }
+        }
+        Err(Error::NotFound)
+    }
+
+    /// cancel the running sub coroutines that match the predicate,
+    /// return the number of the cancelled ones
+    pub fn cancel_where<F>(&self, mut pred: F) -> usize
+    where
+        F: FnMut(&ChildInfo) -> bool,
+    {
+        let stopping = self.inner.token.is_cancelled();
+        let mut n = 0;
+        self.inner.co_list.iter().for_each(|node| {
+            if let Node::Co(child) = &**node {
+                if pred(&child.info(stopping)) && child.cancel() {
+                    n += 1;
+                }
+            }
+        });
+        n
+    }
+
+    /// cancel all the running sub coroutines, the manager is still usable
+    ///
+    /// the sub managers are not affected, return the number of the cancelled ones
+    pub fn cancel_all(&self) -> usize {
+        self.cancel_where(|_| true)
+    }
+
+    /// get a snapshot of the statistics of the sub coroutines
+    pub fn stats(&self) -> ManagerStats {
+        self.inner.stats.snapshot(self.len())
+    }
+
+    /// get a snapshot of the manager hierarchy
+    pub fn tree(&self) -> ManagerTree {
+        self.inner.tree()
+    }
+
+    /// set how the panics of the sub coroutines are handled
+    pub fn set_panic_policy(&self, policy: PanicPolicy) {
+        self.inner.panic_policy.write(policy);
+    }
+
+    /// set how the sub coroutines are stopped when the manager is dropped
+    pub fn set_drop_mode(&self, mode: DropMode) {
+        self.inner.drop_mode.write(mode);
+    }
+
+    /// set how long the cleanup registered by `defer` or `on_cancel` may take,
+    /// the cleanup is cancelled when it runs out of the time
+    ///
+    /// by default the cleanup is not limited
+    pub fn set_cleanup_timeout(&self, timeout: Duration) {
+        self.inner.cleanup_timeout.write(timeout);
+    }
+
+    /// request all the sub coroutines to stop and wait up to `grace` for them
+    /// to exit, then cancel the ones that are still running
+    ///
+    /// the sub coroutines can poll the request by `stop_requested` or their `CancelToken`
+    pub fn shutdown(self, grace: Duration) {
+        self.set_drop_mode(DropMode::Graceful(grace));
+    }
+
+    // update the hooks with a copy of the current ones
+    fn set_hook(&self, f: impl FnOnce(&mut Hooks)) {
+        self.inner.hooks.update(|old| {
+            let mut hooks = old.map(|h| (*h).clone()).unwrap_or_default();
+            f(&mut hooks);
+            Some(hooks)
+        });
+    }
+
+    /// call the closure when a sub coroutine is spawned
+    ///
+    /// the hooks are called in the context that triggers the event, they should
+    /// be fast and never panic
+    pub fn on_spawn<F>(&self, f: F)
+    where
+        F: Fn(&ChildInfo) + Send + Sync + 'static,
+    {
+        self.set_hook(|hooks| hooks.on_spawn = Some(Arc::new(f)));
+    }
+
+    /// call the closure when a sub coroutine exits, in the sub coroutine context
+    pub fn on_exit<F>(&self, f: F)
+    where
+        F: Fn(&ChildInfo, &ExitReason) + Send + Sync + 'static,
+    {
+        self.set_hook(|hooks| hooks.on_exit = Some(Arc::new(f)));
+    }
+
+    /// call the closure when a running sub coroutine is cancelled, by the
+    /// manager, its handle or its deadline
+    pub fn on_cancel<F>(&self, f: F)
+    where
+        F: Fn(&ChildInfo) + Send + Sync + 'static,
+    {
+        self.set_hook(|hooks| hooks.on_cancel = Some(Arc::new(f)));
+    }
+
+    /// call the closure when the manager starts to drop
+    pub fn on_drop_start<F>(&self, f: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.set_hook(|hooks| hooks.on_drop_start = Some(Arc::new(f)));
+    }
+
+    /// call the closure after the manager stopped all its sub coroutines
+    pub fn on_drop_done<F>(&self, f: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.set_hook(|hooks| hooks.on_drop_done = Some(Arc::new(f)));
+    }
+
+    /// subscribe the exit events of the sub coroutines
+    ///
+    /// an event is sent after the sub coroutine is removed from the manager,
+    /// the panic payload in the event only keeps the panic message
+    pub fn exit_events(&self) -> Receiver<ExitEvent> {
+        let (tx, rx) = channel();
+        self.inner.events.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// take the panic payloads recorded by `PanicPolicy::Record` or `PanicPolicy::Escalate`
+    pub fn take_panics(&self) -> Vec<PanicPayload> {
+        std::mem::take(&mut *self.inner.panics.lock().unwrap())
     }
 
     pub fn add<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let slot = Arc::new(RcuCell::none());
-        let slot_dup = slot.clone();
+        self.spawn(f);
+    }
 
-        let co_list = self.co_list.clone();
+    /// add a sub coroutine without blocking
+    ///
+    /// return `Error::Full` if the manager reached its capacity limit,
+    /// or `Error::Closed` if the manager started to shut down
+    pub fn try_add<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_spawn(f).map(drop)
+    }
 
-        let co = go!(move || {
-            let entry = co_list.push_front(slot_dup);
-            let _sub_co = SubCo { entry };
-            f();
-        });
-        // setup the JoinHandle
-        slot.write(co);
+    /// spawn a managed sub coroutine and return a handle to its result
+    ///
+    /// the sub coroutine is still cancelled when the manager is dropped,
+    /// dropping the returned handle just detaches the result
+    ///
+    /// block the caller if the manager reached its capacity limit. if the
+    /// manager started to shut down, the sub coroutine is cancelled before it runs
+    pub fn spawn<F, T>(&self, f: F) -> ManagedHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.acquire_slot();
+        self.spawn_impl(None, None, None, f)
+            .expect("failed to spawn managed coroutine")
+    }
+
+    /// add a sub coroutine that is cancelled if it runs longer than `timeout`
+    pub fn add_with_timeout<F>(&self, timeout: Duration, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.spawn_with_timeout(timeout, f);
+    }
+
+    /// same as `spawn` except that the sub coroutine is cancelled if it runs
+    /// longer than `timeout`
+    ///
+    /// the handle reports the timeout by `ManagedHandle::is_timed_out`
+    pub fn spawn_with_timeout<F, T>(&self, timeout: Duration, f: F) -> ManagedHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.acquire_slot();
+        self.spawn_impl(None, None, Instant::now().checked_add(timeout), f)
+            .expect("failed to spawn managed coroutine")
+    }
+
+    /// spawn a managed sub coroutine without blocking
+    ///
+    /// return `Error::Full` if the manager reached its capacity limit,
+    /// or `Error::Closed` if the manager started to shut down
+    pub fn try_spawn<F, T>(&self, f: F) -> Result<ManagedHandle<T>, Error>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        if self.inner.is_closed() {
+            return Err(Error::Closed);
+        }
+        if let Some(limit) = &self.inner.limit {
+            if !limit.try_wait() {
+                return Err(Error::Full);
+            }
+        }
+        Ok(self
+            .spawn_impl(None, None, None, f)
+            .expect("failed to spawn managed coroutine"))
+    }
+
+    /// create a cloneable spawner that can add sub coroutines to this manager
+    /// from other threads or coroutines
+    pub fn spawner(&self) -> Spawner {
+        Spawner::new(Arc::downgrade(&self.inner))
+    }
+
+    /// create a builder to config the sub coroutine before spawn it
+    pub fn builder(&self) -> Builder<'_> {
+        Builder::new(self)
+    }
+
+    // block until a slot of the capacity limit is available
+    fn acquire_slot(&self) {
+        self.inner.acquire_slot();
+    }
+
+    // the slot of the capacity limit is already acquired
+    fn spawn_impl<F, T>(
+        &self,
+        name: Option<String>,
+        stack_size: Option<usize>,
+        deadline: Option<Instant>,
+        f: F,
+    ) -> io::Result<ManagedHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.inner.spawn_impl(name, stack_size, deadline, f)
+    }
+
+    /// add a sub coroutine that receives a `CancelToken`
+    ///
+    /// the token is cancelled when the manager is dropped or shutdown, before
+    /// the sub coroutine is hard cancelled
+    pub fn add_with_token<F>(&self, f: F)
+    where
+        F: FnOnce(CancelToken) + Send + 'static,
+    {
+        self.spawn_with_token(f);
+    }
+
+    /// same as `spawn` except that the sub coroutine receives a `CancelToken`
+    pub fn spawn_with_token<F, T>(&self, f: F) -> ManagedHandle<T>
+    where
+        F: FnOnce(CancelToken) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let token = self.inner.token.child_token();
+        self.spawn(move || f(token))
     }
 
     /// add sub coroutine that not static
     ///
+    /// prefer `scope` which is the safe way to borrow from the parent
+    ///
     /// # Safety
     ///
-    /// the `SubCo` may not live long enough
+    /// the `SubCo` may not live long enough, and if the manager is dropped by
+    /// a cancelled coroutine it can't wait the sub coroutines to exit
     pub unsafe fn add_unsafe<'a, F>(&self, f: F)
     where
         F: FnOnce() + Send + 'a,
     {
-        let slot = Arc::new(RcuCell::none());
-        let slot_dup = slot.clone();
-
-        let co_list = self.co_list.clone();
-
         let closure: Box<dyn FnOnce() + Send + 'a> = Box::new(f);
         let closure: Box<dyn FnOnce() + Send> = std::mem::transmute(closure);
-
-        let co = go!(move || {
-            let entry = co_list.push_front(slot_dup);
-            let _sub_co = SubCo { entry };
-            closure()
-        });
-        // setup the JoinHandle
-        slot.write(co);
+        self.spawn(closure);
     }
 }
 
 impl Drop for Manager {
     // when parent exit would call this drop
     fn drop(&mut self) {
-        // cancel all the sub coroutines
-        self.co_list.iter().for_each(|co| {
-            let co = co.read().unwrap();
-            unsafe { co.coroutine().cancel() };
-            co.wait()
-        });
+        let grace = match self.inner.drop_mode.read().as_deref() {
+            Some(DropMode::Graceful(grace)) => Some(*grace),
+            _ => None,
+        };
+        let hooks = self.inner.hooks.read();
+        if let Some(on_drop_start) = hooks.as_ref().and_then(|h| h.on_drop_start.as_ref()) {
+            on_drop_start();
+        }
+        self.inner.stop(grace);
+        self.inner.timer.close();
+        if let Some(on_drop_done) = hooks.as_ref().and_then(|h| h.on_drop_done.as_ref()) {
+            on_drop_done();
+        }
+        // unlink from the parent manager
+        if let Some(link) = self.link.take() {
+            link.remove();
+        }
+        self.inner.check_escalated();
+    }
+}
+
+// the manager of a sub coroutine and its entry in the manager list
+struct Owner {
+    inner: Arc<Inner>,
+    entry: StaticEntry<CoNode>,
+}
 
-        // the SubCo drop would remove itself from the list
-        while !self.co_list.is_empty() {
-            coroutine::yield_now();
+impl Owner {
+    // unregister the sub coroutine from the manager
+    fn release(self) {
+        self.entry.remove();
+        self.release_slot();
+        self.inner.alive.dec();
+    }
+
+    // release the slot of the capacity limit, the cleanup doesn't take one
+    fn release_slot(&self) {
+        if let Node::Co(child) = &**self.entry {
+            if child.is_cleanup() {
+                return;
+            }
+        }
+        if let Some(limit) = &self.inner.limit {
+            limit.post();
         }
     }
 }
 
 /// represent a managed sub coroutine
-pub struct SubCo<'a> {
-    entry: Entry<'a, CoNode>,
+pub struct SubCo {
+    node: CoNode,
+    reason: Option<ExitReason>,
 }
 
-impl Drop for SubCo<'_> {
+impl Drop for SubCo {
     // when the sub coroutine finished will trigger this drop
     fn drop(&mut self) {
-        self.entry.remove();
+        let Node::Co(child) = &*self.node else {
+            return;
+        };
+        child.finish();
+        // the detached sub coroutine is already unregistered
+        let Some(owner) = child.owner().take() else {
+            return;
+        };
+        owner.entry.remove();
+        owner.release_slot();
+        let inner = owner.inner;
+        // run the queued cleanups one by one in drop order, the manager waits
+        // them since they are spawned before the sub coroutine is unregistered
+        let cleanups = child.take_cleanups();
+        if !cleanups.is_empty() {
+            inner
+                .spawn_cleanup(move || cleanups.into_iter().for_each(|f| f()))
+                .expect("failed to spawn managed coroutine");
+        }
+        // the sub coroutine is cancelled before it starts running
+        let reason = self.reason.take().unwrap_or_else(|| {
+            if child.is_timed_out() {
+                ExitReason::TimedOut
+            } else {
+                ExitReason::Cancelled
+            }
+        });
+        if child.is_cleanup() {
+            inner.cleanups.fetch_sub(1, Ordering::AcqRel);
+        } else {
+            let lifetime = child.spawned_at().elapsed();
+            inner.stats.exit(&reason, lifetime);
+            inner.notify_exit(&self.node, &reason);
+        }
+        inner.alive.dec();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
+    use std::time::Instant;
 
     #[test]
     fn thread_exit() {
@@ -159,4 +1138,840 @@ mod tests {
         println!("parent exit");
         coroutine::sleep(Duration::from_millis(1000));
     }
+
+    #[test]
+    fn spawn_join() {
+        let manager = Manager::new();
+        let h = manager.spawn(|| {
+            coroutine::sleep(Duration::from_millis(10));
+            42
+        });
+        assert_eq!(h.join().unwrap(), 42);
+
+        let h = manager.spawn(|| loop {
+            coroutine::sleep(Duration::from_millis(10));
+        });
+        assert!(!h.is_done());
+        h.cancel();
+        h.wait();
+        assert!(h.is_done());
+        assert!(h.join().is_err());
+    }
+
+    #[test]
+    fn spawn_cancelled_by_drop() {
+        let manager = Manager::new();
+        let h = manager.spawn(|| loop {
+            coroutine::sleep(Duration::from_millis(10));
+        });
+        coroutine::sleep(Duration::from_millis(20));
+        drop(manager);
+        assert!(h.join().is_err());
+    }
+
+    #[test]
+    fn panic_record() {
+        let manager = Manager::new();
+        manager.set_panic_policy(PanicPolicy::Record);
+        let h = manager.spawn(|| panic!("boom"));
+        let err = h.join().unwrap_err();
+        assert_eq!(panic_message(&*err), "boom");
+        let panics = manager.take_panics();
+        assert_eq!(panics.len(), 1);
+        assert_eq!(panic_message(&*panics[0]), "boom");
+        assert!(manager.take_panics().is_empty());
+    }
+
+    #[test]
+    fn panic_hook() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let count_dup = count.clone();
+        let manager = Manager::new();
+        manager.set_panic_policy(PanicPolicy::hook(move |payload| {
+            assert_eq!(panic_message(payload), "boom");
+            count_dup.fetch_add(1, Ordering::Relaxed);
+        }));
+        let h = manager.spawn(|| panic!("boom"));
+        assert!(h.join().is_err());
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        // cancellation is not reported as panic
+        let h = manager.spawn(|| loop {
+            coroutine::sleep(Duration::from_millis(10));
+        });
+        h.cancel();
+        assert!(h.join().is_err());
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn panic_escalate() {
+        let manager = Manager::new();
+        manager.set_panic_policy(PanicPolicy::Escalate);
+        let sibling = manager.spawn(|| loop {
+            coroutine::sleep(Duration::from_millis(10));
+        });
+        coroutine::sleep(Duration::from_millis(20));
+        let h = manager.spawn(|| panic!("boom"));
+        assert!(h.join().is_err());
+        // the sibling is cancelled by the panic
+        assert!(sibling.join().is_err());
+
+        let err = std::panic::catch_unwind(AssertUnwindSafe(|| drop(manager))).unwrap_err();
+        assert_eq!(panic_message(&*err), "boom");
+    }
+
+    #[test]
+    fn graceful_shutdown() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let flushed = Arc::new(AtomicUsize::new(0));
+        let manager = Manager::new();
+        for _ in 0..10 {
+            let flushed = flushed.clone();
+            manager.add(move || {
+                while !stop_requested() {
+                    coroutine::sleep(Duration::from_millis(10));
+                }
+                flushed.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        // a stubborn one that would be cancelled after the grace period
+        let stubborn = manager.spawn(|| loop {
+            coroutine::sleep(Duration::from_millis(10));
+        });
+        coroutine::sleep(Duration::from_millis(50));
+        assert!(!stop_requested());
+
+        let start = Instant::now();
+        manager.shutdown(Duration::from_millis(200));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(flushed.load(Ordering::Relaxed), 10);
+        assert!(stubborn.join().is_err());
+    }
+
+    #[test]
+    fn graceful_drop_mode() {
+        let manager = Manager::new();
+        manager.set_drop_mode(DropMode::Graceful(Duration::from_secs(10)));
+        let h = manager.spawn(|| {
+            while !stop_requested() {
+                coroutine::sleep(Duration::from_millis(10));
+            }
+            "done"
+        });
+        coroutine::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        drop(manager);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(h.join().unwrap(), "done");
+    }
+
+    #[test]
+    fn graceful_drop_sub_manager() {
+        use std::sync::atomic::AtomicBool;
+
+        let flushed = Arc::new(AtomicBool::new(false));
+        let manager = Manager::new();
+        manager.set_drop_mode(DropMode::Graceful(Duration::from_secs(2)));
+        let sub = manager.child();
+        let done = flushed.clone();
+        sub.add(move || {
+            while !stop_requested() {
+                coroutine::sleep(Duration::from_millis(10));
+            }
+            // the flush still gets the grace period of the parent
+            coroutine::sleep(Duration::from_millis(100));
+            done.store(true, Ordering::SeqCst);
+        });
+        coroutine::sleep(Duration::from_millis(20));
+
+        let start = Instant::now();
+        drop(manager);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(flushed.load(Ordering::SeqCst));
+        drop(sub);
+    }
+
+    #[test]
+    fn graceful_drop_cancelled() {
+        use std::sync::atomic::AtomicBool;
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = channel();
+        let done = dropped.clone();
+        let parent = go!(move || {
+            let manager = Manager::new();
+            manager.set_drop_mode(DropMode::Graceful(Duration::from_secs(10)));
+            // the straggler ignores the stop request
+            tx.send(manager.spawn(|| coroutine::sleep(Duration::from_secs(10))))
+                .unwrap();
+            drop(manager);
+            done.store(true, Ordering::SeqCst);
+        });
+        let straggler = rx.recv().unwrap();
+        coroutine::sleep(Duration::from_millis(20));
+
+        // cancel the parent in the grace period
+        let start = Instant::now();
+        unsafe { parent.coroutine().cancel() };
+        parent.join().ok();
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(straggler.join().is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn cancel_token() {
+        let manager = Manager::new();
+        manager.set_drop_mode(DropMode::Graceful(Duration::from_secs(10)));
+        let mut handles = vec![];
+        for i in 0..10 {
+            handles.push(manager.spawn_with_token(move |token| {
+                while !token.is_cancelled() {
+                    coroutine::sleep(Duration::from_millis(10));
+                }
+                i
+            }));
+        }
+        let h = manager.spawn_with_token(|token| {
+            token.cancelled();
+            "cancelled"
+        });
+        coroutine::sleep(Duration::from_millis(50));
+        drop(manager);
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), i);
+        }
+        assert_eq!(h.join().unwrap(), "cancelled");
+    }
+
+    #[test]
+    fn nested_managers() {
+        let forever = || loop {
+            coroutine::sleep(Duration::from_millis(10));
+        };
+        let manager = Manager::new();
+        let child = manager.child();
+        let grand_child = child.child();
+        manager.add(forever);
+        let h1 = child.spawn(forever);
+        let h2 = grand_child.spawn(forever);
+        coroutine::sleep(Duration::from_millis(20));
+
+        let leaf = ManagerTree {
+            coroutines: 1,
+            children: vec![],
+        };
+        let tree = ManagerTree {
+            coroutines: 1,
+            children: vec![ManagerTree {
+                coroutines: 1,
+                children: vec![leaf.clone()],
+            }],
+        };
+        assert_eq!(manager.tree(), tree);
+
+        // drop the sub manager would unlink it from the parent
+        let other = manager.child();
+        assert_eq!(manager.tree().children.len(), 2);
+        drop(other);
+        assert_eq!(manager.tree(), tree);
+
+        // drop the parent would cascade to all the sub managers
+        drop(manager);
+        assert!(h1.join().is_err());
+        assert!(h2.join().is_err());
+        assert_eq!(child.tree(), ManagerTree::default());
+        assert_eq!(grand_child.tree(), ManagerTree::default());
+    }
+
+    #[test]
+    fn capacity_limit() {
+        let forever = || loop {
+            coroutine::sleep(Duration::from_millis(10));
+        };
+        let manager = Arc::new(Manager::with_capacity_limit(2));
+        let h1 = manager.spawn(forever);
+        manager.add(forever);
+        assert_eq!(manager.try_add(forever), Err(Error::Full));
+
+        // the blocking add would wait for a free slot
+        let manager_dup = manager.clone();
+        let j = go!(move || manager_dup.spawn(|| 42).join().unwrap());
+        coroutine::sleep(Duration::from_millis(50));
+        assert!(!j.is_done());
+
+        h1.cancel();
+        assert_eq!(j.join().unwrap(), 42);
+        // the slot of the finished sub coroutine is released
+        manager.try_spawn(|| ()).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn children_info() {
+        let manager = Manager::new();
+        assert!(manager.is_empty());
+        let start = Instant::now();
+        let handles: Vec<_> = (0..5)
+            .map(|_| {
+                manager.spawn(|| loop {
+                    coroutine::sleep(Duration::from_millis(10));
+                })
+            })
+            .collect();
+        // sub managers are not counted
+        let _child = manager.child();
+        coroutine::sleep(Duration::from_millis(20));
+        assert_eq!(manager.len(), 5);
+        assert!(!manager.is_empty());
+
+        let mut ids: Vec<_> = manager
+            .children()
+            .map(|info| {
+                assert_eq!(info.state, ChildState::Running);
+                assert!(info.spawned_at >= start);
+                info.id
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 5);
+
+        for h in handles {
+            h.cancel();
+            h.join().ok();
+        }
+        assert_eq!(manager.len(), 0);
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn builder_spawn() {
+        let manager = Manager::new();
+        let h = manager
+            .builder()
+            .name("conn-42")
+            .stack_size(64 * 1024)
+            .spawn(|| {
+                coroutine::sleep(Duration::from_millis(50));
+                coroutine::current().name().map(|s| s.to_owned())
+            })
+            .unwrap();
+        assert_eq!(h.coroutine().name(), Some("conn-42"));
+        assert_eq!(h.coroutine().stack_size(), 64 * 1024);
+        coroutine::sleep(Duration::from_millis(10));
+        let info = manager.children().next().unwrap();
+        assert_eq!(info.name.as_deref(), Some("conn-42"));
+        assert_eq!(h.join().unwrap().as_deref(), Some("conn-42"));
+    }
+
+    #[test]
+    fn join_all() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let done = Arc::new(AtomicUsize::new(0));
+        let manager = Manager::new();
+        for i in 0..10 {
+            let done = done.clone();
+            manager.add(move || {
+                coroutine::sleep(Duration::from_millis(10 * i));
+                done.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        manager.join_all();
+        assert_eq!(done.load(Ordering::Relaxed), 10);
+        assert!(manager.is_empty());
+        // join an idle manager returns immediately
+        manager.join_all();
+
+        // join in a coroutine
+        let manager = Arc::new(manager);
+        let manager_dup = manager.clone();
+        let done_dup = done.clone();
+        manager.add(move || {
+            coroutine::sleep(Duration::from_millis(20));
+            done_dup.fetch_add(1, Ordering::Relaxed);
+        });
+        go!(move || manager_dup.join_all()).join().unwrap();
+        assert_eq!(done.load(Ordering::Relaxed), 11);
+    }
+
+    #[test]
+    fn wait_idle() {
+        let manager = Manager::new();
+        let h = manager.spawn(|| loop {
+            coroutine::sleep(Duration::from_millis(10));
+        });
+        let start = Instant::now();
+        assert!(!manager.wait_idle(Duration::from_millis(50)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        // not cancelled by the wait
+        assert!(!h.is_done());
+        h.cancel();
+        assert!(manager.wait_idle(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn join_all_escalate() {
+        let manager = Manager::new();
+        manager.set_panic_policy(PanicPolicy::Escalate);
+        manager.add(|| loop {
+            coroutine::sleep(Duration::from_millis(10));
+        });
+        manager.add(|| {
+            coroutine::sleep(Duration::from_millis(20));
+            panic!("boom");
+        });
+        let err = std::panic::catch_unwind(AssertUnwindSafe(|| manager.join_all())).unwrap_err();
+        assert_eq!(panic_message(&*err), "boom");
+    }
+
+    #[test]
+    fn drop_in_cancelled_coroutine() {
+        let (tx, rx) = may::sync::mpsc::channel();
+        let j = go!(move || {
+            let manager = Manager::new();
+            for _ in 0..10 {
+                let h = manager.spawn(|| loop {
+                    coroutine::sleep(Duration::from_millis(10));
+                });
+                tx.send(h).unwrap();
+            }
+            coroutine::park();
+        });
+        let handles: Vec<_> = rx.iter().take(10).collect();
+        coroutine::sleep(Duration::from_millis(20));
+        unsafe { j.coroutine().cancel() };
+        assert!(j.join().is_err());
+        // the sub coroutines are cancelled even the parent can't wait them
+        for h in handles {
+            assert!(h.join().is_err());
+        }
+    }
+
+    #[test]
+    fn drop_while_spawning() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static ALIVE: AtomicUsize = AtomicUsize::new(0);
+        struct Guard;
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                ALIVE.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+
+        let forever = || {
+            ALIVE.fetch_add(1, Ordering::SeqCst);
+            let _guard = Guard;
+            loop {
+                coroutine::sleep(Duration::from_millis(10));
+            }
+        };
+
+        for _ in 0..200 {
+            let manager = Manager::new();
+            for _ in 0..20 {
+                manager.add(forever);
+            }
+            // the sub coroutines may not start running yet
+            drop(manager);
+            assert_eq!(ALIVE.load(Ordering::SeqCst), 0);
+        }
+
+        // drop in a coroutine
+        go!(move || {
+            for _ in 0..200 {
+                let manager = Manager::new();
+                for _ in 0..20 {
+                    manager.add(forever);
+                }
+                drop(manager);
+                assert_eq!(ALIVE.load(Ordering::SeqCst), 0);
+            }
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn child_timeout() {
+        let manager = Manager::new();
+        let slow = manager.spawn_with_timeout(Duration::from_millis(20), || {
+            coroutine::sleep(Duration::from_secs(10));
+        });
+        let fast = manager.spawn_with_timeout(Duration::from_millis(20), || 42);
+        let forever = manager.spawn(|| coroutine::sleep(Duration::from_millis(100)));
+
+        slow.wait();
+        assert!(slow.is_timed_out());
+        assert!(slow.join().is_err());
+        // the finished sub coroutine never times out
+        coroutine::sleep(Duration::from_millis(30));
+        assert!(!fast.is_timed_out());
+        assert_eq!(fast.join().unwrap(), 42);
+        assert!(!forever.is_done());
+        assert_eq!(manager.len(), 1);
+    }
+
+    #[test]
+    fn manager_deadline() {
+        let start = Instant::now();
+        let manager = Manager::with_deadline(start + Duration::from_millis(50));
+        let h1 = manager.spawn(|| coroutine::sleep(Duration::from_secs(10)));
+        // the earlier timeout of the sub coroutine wins
+        let h2 = manager.spawn_with_timeout(Duration::from_millis(10), || {
+            coroutine::sleep(Duration::from_secs(10));
+        });
+        let sub = manager.child();
+        let h3 = sub.spawn(|| coroutine::sleep(Duration::from_secs(10)));
+
+        h2.wait();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        h1.wait();
+        h3.wait();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(h1.is_timed_out() && h2.is_timed_out() && h3.is_timed_out());
+
+        // spawn after the deadline
+        let h = manager.spawn(|| coroutine::sleep(Duration::from_secs(10)));
+        h.wait();
+        assert!(h.is_timed_out());
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn exit_reason() {
+        let manager = Manager::new();
+        let done = manager.spawn(|| 1);
+        let panicked = manager.spawn(|| panic!("boom"));
+        let timed_out = manager.spawn_with_timeout(Duration::from_millis(10), || {
+            coroutine::sleep(Duration::from_secs(10));
+        });
+        let cancelled = manager.spawn(|| coroutine::sleep(Duration::from_secs(10)));
+        cancelled.cancel();
+
+        assert!(matches!(done.exit_reason(), ExitReason::Completed));
+        match panicked.exit_reason() {
+            ExitReason::Panicked(payload) => assert_eq!(panic_message(&*payload), "boom"),
+            reason => panic!("unexpected exit reason {reason:?}"),
+        }
+        assert!(matches!(timed_out.exit_reason(), ExitReason::TimedOut));
+        assert!(matches!(cancelled.exit_reason(), ExitReason::Cancelled));
+    }
+
+    #[test]
+    fn exit_events() {
+        let manager = Manager::new();
+        let events = manager.exit_events();
+        let h = manager
+            .builder()
+            .name("done")
+            .spawn(|| coroutine::sleep(Duration::from_millis(10)))
+            .unwrap();
+        manager.add(|| panic!("boom"));
+        manager.add_with_timeout(Duration::from_millis(10), || {
+            coroutine::sleep(Duration::from_secs(10));
+        });
+        manager.add(|| coroutine::sleep(Duration::from_secs(10)));
+
+        let mut reasons = Vec::new();
+        for _ in 0..3 {
+            let event = events.recv().unwrap();
+            if let ExitReason::Completed = event.reason {
+                assert_eq!(event.name.as_deref(), Some("done"));
+            }
+            reasons.push(format!("{:?}", event.reason));
+        }
+        reasons.sort();
+        assert_eq!(reasons, ["Completed", "Panicked(\"boom\")", "TimedOut"]);
+        h.join().unwrap();
+
+        drop(manager);
+        let event = events.recv().unwrap();
+        assert!(matches!(event.reason, ExitReason::Cancelled));
+        // the manager is dropped with the senders
+        assert!(events.recv().is_err());
+    }
+
+    #[test]
+    fn lifecycle_hooks() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let manager = Manager::new();
+        let push = |log: &Arc<Mutex<Vec<String>>>, event: String| log.lock().unwrap().push(event);
+
+        let l = log.clone();
+        manager.on_spawn(move |info| push(&l, format!("spawn {:?}", info.name)));
+        let l = log.clone();
+        manager.on_exit(move |info, reason| push(&l, format!("exit {:?} {reason:?}", info.name)));
+        let l = log.clone();
+        manager.on_cancel(move |info| push(&l, format!("cancel {:?}", info.name)));
+        let l = log.clone();
+        manager.on_drop_start(move || push(&l, "drop start".into()));
+        let l = log.clone();
+        manager.on_drop_done(move || push(&l, "drop done".into()));
+
+        let h = manager.builder().name("a").spawn(|| {}).unwrap();
+        h.join().unwrap();
+        // the exit hook runs before the sub coroutine is unregistered
+        manager.join_all();
+        manager
+            .builder()
+            .name("b")
+            .spawn(|| coroutine::sleep(Duration::from_secs(10)))
+            .unwrap();
+        coroutine::sleep(Duration::from_millis(10));
+        drop(manager);
+
+        let log = log.lock().unwrap();
+        assert_eq!(
+            *log,
+            [
+                "spawn Some(\"a\")",
+                "exit Some(\"a\") Completed",
+                "spawn Some(\"b\")",
+                "drop start",
+                "cancel Some(\"b\")",
+                "exit Some(\"b\") Cancelled",
+                "drop done",
+            ]
+        );
+    }
+
+    #[test]
+    fn manager_stats() {
+        let manager = Manager::new();
+        for _ in 0..3 {
+            manager.add(|| coroutine::sleep(Duration::from_millis(20)));
+        }
+        manager.add(|| {
+            coroutine::sleep(Duration::from_millis(10));
+            panic!("boom");
+        });
+        manager.add_with_timeout(Duration::from_millis(10), || {
+            coroutine::sleep(Duration::from_secs(10));
+        });
+        let h = manager.spawn(|| coroutine::sleep(Duration::from_secs(10)));
+        h.cancel();
+        manager.join_all();
+
+        let stats = manager.stats();
+        assert_eq!(stats.spawned, 6);
+        assert_eq!(stats.alive, 0);
+        assert_eq!(stats.peak, 6);
+        assert_eq!(stats.completed, 3);
+        assert_eq!(stats.panicked, 1);
+        assert_eq!(stats.cancelled, 1);
+        assert_eq!(stats.timed_out, 1);
+        assert_eq!(stats.lifetimes.count(), 6);
+        // all but the cancelled one lived at least 10ms
+        assert_eq!(stats.lifetimes.counts()[2..].iter().sum::<u64>(), 5);
+    }
+
+    #[test]
+    fn cancel_by_id() {
+        let manager = Manager::new();
+        let forever = || coroutine::sleep(Duration::from_secs(10));
+        let a = manager.builder().name("a").spawn(forever).unwrap();
+        let b = manager.builder().name("b").spawn(forever).unwrap();
+        let c = manager.builder().name("conn-c").spawn(forever).unwrap();
+
+        let id = a.id();
+        assert!(manager.cancel(id));
+        assert!(a.join().is_err());
+        assert!(!manager.cancel(id));
+        assert!(!b.is_done());
+
+        let n = manager.cancel_where(|info| info.name.as_deref().unwrap().starts_with("conn-"));
+        assert_eq!(n, 1);
+        assert!(c.join().is_err());
+        assert!(!b.is_done());
+
+        // the manager is still usable
+        let d = manager.spawn(|| 42);
+        assert_eq!(d.join().unwrap(), 42);
+        assert_eq!(manager.cancel_all(), 1);
+        assert!(b.join().is_err());
+        assert_eq!(manager.spawn(|| 1).join().unwrap(), 1);
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn cancel_counts_once() {
+        let manager = Manager::new();
+        let spin = Arc::new(AtomicBool::new(true));
+        let flag = spin.clone();
+        let (tx, rx) = channel();
+        // the cancel is only seen at a yield point, the child keeps running
+        let a = manager.spawn(move || {
+            tx.send(()).unwrap();
+            while flag.load(Ordering::SeqCst) {
+                std::hint::spin_loop();
+            }
+            coroutine::yield_now();
+        });
+        rx.recv().unwrap();
+        // stop the spin even if the asserts fail
+        struct Stop(Arc<AtomicBool>);
+        impl Drop for Stop {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::SeqCst);
+            }
+        }
+        let stop = Stop(spin);
+        assert!(manager.cancel(a.id()));
+        assert!(!manager.cancel(a.id()));
+        assert_eq!(manager.cancel_all(), 0);
+        assert_eq!(manager.cancel_where(|_| true), 0);
+        drop(stop);
+        assert!(a.join().is_err());
+        assert_eq!(manager.stats().cancelled, 1);
+    }
+
+    #[test]
+    fn detach_child() {
+        let manager = Manager::new();
+        let a = manager.spawn(|| {
+            coroutine::sleep(Duration::from_millis(50));
+            42
+        });
+        let b = manager.spawn(|| coroutine::sleep(Duration::from_secs(10)));
+        let c = manager.spawn(|| coroutine::sleep(Duration::from_secs(10)));
+        assert!(manager.detach(b.id()));
+        assert!(!manager.detach(b.id()));
+        assert!(manager.cancel(c.id()));
+        assert!(!manager.detach(c.id()));
+        let a = a.detach();
+        assert!(c.join().is_err());
+        assert!(manager.is_empty());
+        // the detached ones are counted as gone
+        let stats = manager.stats();
+        assert_eq!(stats.spawned, 3);
+        assert_eq!(stats.detached, 2);
+        assert_eq!(stats.cancelled, 1);
+        assert_eq!(stats.alive, 0);
+
+        // the detached ones outlive the manager
+        drop(manager);
+        assert_eq!(a.join().unwrap(), 42);
+        assert!(!b.is_done());
+        b.cancel();
+        assert!(b.join().is_err());
+    }
+
+    #[test]
+    fn transfer_child() {
+        let session = Manager::with_capacity_limit(1);
+        let events = session.exit_events();
+        let request = Manager::with_deadline(Instant::now() + Duration::from_millis(30));
+        let a = request.spawn(|| {
+            coroutine::sleep(Duration::from_millis(60));
+            42
+        });
+        let b = request.spawn(|| coroutine::sleep(Duration::from_secs(10)));
+        let id = a.id();
+        assert_eq!(session.adopt(&a), Ok(()));
+        assert_eq!(session.len(), 1);
+        assert_eq!(request.len(), 1);
+        assert_eq!(request.transfer(b.id(), &session), Err(Error::Full));
+        assert_eq!(request.transfer(id, &session), Err(Error::NotFound));
+
+        // the moved one is not bound to the request deadline and lifetime
+        assert!(b.join().is_err());
+        let stats = request.stats();
+        assert_eq!(stats.spawned, 2);
+        assert_eq!(stats.transferred, 1);
+        assert_eq!(stats.timed_out, 1);
+        assert_eq!(stats.alive, 0);
+        drop(request);
+        assert_eq!(a.join().unwrap(), 42);
+        let event = events.recv().unwrap();
+        assert_eq!(event.id, id);
+        assert!(matches!(event.reason, ExitReason::Completed));
+        assert!(session.is_empty());
+
+        // the moved one exits in the new manager only
+        let stats = session.stats();
+        assert_eq!((stats.spawned, stats.completed), (1, 1));
+        assert_eq!(stats.transferred, 0);
+    }
+
+    #[test]
+    fn spawn_during_drop() {
+        use std::sync::atomic::AtomicUsize;
+
+        static LATE: AtomicUsize = AtomicUsize::new(0);
+        let forever = || coroutine::sleep(Duration::from_secs(10));
+        let start = Instant::now();
+        {
+            // dropped in place, the sub coroutines borrow it during the drop
+            let manager = Manager::new();
+            let m = &manager;
+            for _ in 0..10 {
+                let f = move || {
+                    // spawn after the cancel pass of the drop
+                    struct Late<'a>(&'a Manager);
+                    impl Drop for Late<'_> {
+                        fn drop(&mut self) {
+                            let h = self.0.spawn(|| LATE.fetch_add(1, Ordering::SeqCst));
+                            h.wait();
+                        }
+                    }
+                    let _late = Late(m);
+                    forever();
+                };
+                unsafe { manager.add_unsafe(f) };
+            }
+            coroutine::sleep(Duration::from_millis(20));
+        }
+        // the late ones are cancelled before they run
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(LATE.load(Ordering::SeqCst), 0);
+
+        let closed = Arc::new(AtomicBool::new(false));
+        {
+            let manager = Manager::new();
+            manager.set_drop_mode(DropMode::Graceful(Duration::from_secs(1)));
+            let m = &manager;
+            let closed = closed.clone();
+            let f = move |token: CancelToken| {
+                token.cancelled();
+                let ret = m.try_add(forever);
+                closed.store(ret == Err(Error::Closed), Ordering::SeqCst);
+            };
+            let f: Box<dyn FnOnce(CancelToken) + Send> = Box::new(f);
+            let f: Box<dyn FnOnce(CancelToken) + Send> = unsafe { std::mem::transmute(f) };
+            manager.add_with_token(f);
+        }
+        assert!(closed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn spawner_during_drop() {
+        let manager = Manager::new();
+        let spawner = manager.spawner();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let spawner = spawner.clone();
+                std::thread::spawn(move || {
+                    let forever = || coroutine::sleep(Duration::from_secs(10));
+                    while spawner.add(forever).is_ok() {}
+                })
+            })
+            .collect();
+        coroutine::sleep(Duration::from_millis(20));
+
+        let start = Instant::now();
+        drop(manager);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(spawner.add(|| {}), Err(Error::Closed));
+    }
 }
@@ -0,0 +1,63 @@
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+/// the panic payload of a sub coroutine
+pub type PanicPayload = Box<dyn Any + Send>;
+
+type PanicHook = Arc<dyn Fn(&(dyn Any + Send)) + Send + Sync>;
+
+/// how a `Manager` deals with panics of its sub coroutines
+#[derive(Clone, Default)]
+pub enum PanicPolicy {
+    /// the panic is only visible through the sub coroutine handle
+    #[default]
+    Ignore,
+    /// the panic payloads are kept by the manager, see `Manager::take_panics`
+    Record,
+    /// cancel all the siblings and re-raise the panic in the parent
+    /// when the manager is dropped
+    Escalate,
+    /// call the hook with the panic payload
+    Hook(PanicHook),
+}
+
+impl PanicPolicy {
+    /// create a `PanicPolicy::Hook` from a closure
+    pub fn hook<F>(f: F) -> Self
+    where
+        F: Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        PanicPolicy::Hook(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for PanicPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PanicPolicy::Ignore => f.pad("Ignore"),
+            PanicPolicy::Record => f.pad("Record"),
+            PanicPolicy::Escalate => f.pad("Escalate"),
+            PanicPolicy::Hook(_) => f.pad("Hook(..)"),
+        }
+    }
+}
+
+/// return true if the payload is the cancel panic of may
+pub(crate) fn is_cancel(payload: &(dyn Any + Send)) -> bool {
+    matches!(
+        payload.downcast_ref::<generator::Error>(),
+        Some(generator::Error::Cancel)
+    )
+}
+
+/// get the panic message from the payload
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&'static str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{ManagedHandle, Manager, PanicPolicy};
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+
+/// a scope to spawn sub coroutines that borrow the local variables
+///
+/// see `scope` for details
+pub struct Scope<'scope, 'env: 'scope> {
+    manager: Manager,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// create a scope to spawn sub coroutines that borrow the local variables
+///
+/// all the sub coroutines are finished before `scope` returns. if the closure
+/// panics or the caller is cancelled, the sub coroutines are cancelled and
+/// joined before the panic is resumed. a panic in a sub coroutine cancels the
+/// others and is re-raised by `scope`
+///
+/// a cancelled coroutine can't park, if the caller is cancelled it blocks
+/// its worker thread until the sub coroutines exit on the other workers.
+/// this deadlocks if no other worker is free to run them, e.g. with a single
+/// worker, or when all the workers are blocked by the cancelled scopes
+///
+/// ```rust,no_run
+/// let mut data = vec![1, 2, 3];
+/// let total = std::sync::atomic::AtomicUsize::new(0);
+/// co_managed::scope(|s| {
+///     s.add(|| {
+///         total.fetch_add(data.len(), std::sync::atomic::Ordering::Relaxed);
+///     });
+/// });
+/// data.push(4);
+/// ```
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    let scope = Scope {
+        manager: Manager::scoped(),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    scope.manager.set_panic_policy(PanicPolicy::Escalate);
+
+    let ret = catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    let inner = &scope.manager.inner;
+    if ret.is_err() {
+        inner.token.cancel();
+        inner.cancel_all(None);
+    }
+    inner.alive.wait_all();
+
+    match ret {
+        Ok(ret) => {
+            inner.check_escalated();
+            ret
+        }
+        Err(payload) => resume_unwind(payload),
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    /// add a sub coroutine that may borrow from the scope
+    pub fn add<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        self.spawn(f);
+    }
+
+    /// spawn a sub coroutine that may borrow from the scope and return a
+    /// handle to its result
+    ///
+    /// the sub coroutine can't be detached or moved to another manager
+    pub fn spawn<F, T>(&'scope self, f: F) -> ManagedHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'static,
+    {
+        let f: Box<dyn FnOnce() -> T + Send + 'scope> = Box::new(f);
+        // SAFETY: `scope` waits all the sub coroutines exit before the borrowed
+        // variables go out of scope, even if the caller panics or is cancelled
+        let f: Box<dyn FnOnce() -> T + Send + 'static> = unsafe { std::mem::transmute(f) };
+        self.manager.spawn(f)
+    }
+
+    /// return the number of the running sub coroutines
+    pub fn len(&self) -> usize {
+        self.manager.len()
+    }
+
+    /// return true if there is no running sub coroutines
+    pub fn is_empty(&self) -> bool {
+        self.manager.is_empty()
+    }
+}
+
+impl fmt::Debug for Scope<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scope").field("len", &self.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use may::coroutine;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn borrow_locals() {
+        let mut data = vec![1, 2, 3];
+        let total = AtomicUsize::new(0);
+        let ret = scope(|s| {
+            for v in &data {
+                let total = &total;
+                s.add(move || {
+                    coroutine::sleep(Duration::from_millis(10));
+                    total.fetch_add(*v, Ordering::SeqCst);
+                });
+            }
+            s.spawn(|| data.len())
+        });
+        assert_eq!(total.load(Ordering::SeqCst), 6);
+        assert_eq!(ret.join().unwrap(), 3);
+        data.push(4);
+    }
+
+    #[test]
+    fn detach_refused() {
+        let data = [1, 2, 3];
+        let done = AtomicUsize::new(0);
+        scope(|s| {
+            let h = s.spawn(|| {
+                coroutine::sleep(Duration::from_millis(20));
+                done.store(data.len(), Ordering::SeqCst);
+            });
+            assert!(!s.manager.detach(h.id()));
+            // still joined by the scope
+            drop(h.detach());
+        });
+        assert_eq!(done.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn adopt_refused() {
+        let data = [1, 2, 3];
+        let done = AtomicUsize::new(0);
+        let other = Manager::new();
+        scope(|s| {
+            let h = s.spawn(|| {
+                coroutine::sleep(Duration::from_millis(20));
+                done.store(data.len(), Ordering::SeqCst);
+            });
+            assert_eq!(other.adopt(&h), Err(crate::Error::NotFound));
+            assert_eq!(
+                s.manager.transfer(h.id(), &other),
+                Err(crate::Error::NotFound)
+            );
+            assert!(other.is_empty());
+        });
+        assert_eq!(done.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn panic_cancels_and_joins() {
+        let exited = AtomicUsize::new(0);
+        let start = Instant::now();
+        let ret = catch_unwind(AssertUnwindSafe(|| {
+            scope(|s| {
+                for _ in 0..10 {
+                    s.add(|| {
+                        struct Exit<'a>(&'a AtomicUsize);
+                        impl Drop for Exit<'_> {
+                            fn drop(&mut self) {
+                                self.0.fetch_add(1, Ordering::SeqCst);
+                            }
+                        }
+                        let _exit = Exit(&exited);
+                        coroutine::sleep(Duration::from_secs(10));
+                    });
+                }
+                panic!("scope panic");
+            })
+        }));
+        assert!(ret.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(exited.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "sub panic")]
+    fn sub_panic_escalate() {
+        scope(|s| {
+            s.add(|| coroutine::sleep(Duration::from_secs(10)));
+            s.add(|| panic!("sub panic"));
+        });
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{Error, Inner, ManagedHandle};
+
+use std::fmt;
+use std::sync::{Arc, Weak};
+
+/// a cloneable handle to add sub coroutines to a `Manager`
+///
+/// created by `Manager::spawner`. the spawner only keeps a weak reference,
+/// it never keeps the manager or its sub coroutines alive, and returns
+/// `Error::Closed` once the manager started to shut down
+///
+/// ```rust,no_run
+/// use co_managed::Manager;
+///
+/// let manager = Manager::new();
+/// let spawner = manager.spawner();
+/// let admin = spawner.clone();
+/// std::thread::spawn(move || admin.add(|| { /* admin request */ }));
+/// spawner.add(|| { /* connection */ }).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Spawner {
+    inner: Weak<Inner>,
+}
+
+impl Spawner {
+    pub(crate) fn new(inner: Weak<Inner>) -> Self {
+        Spawner { inner }
+    }
+
+    /// add a sub coroutine to the manager
+    ///
+    /// block the caller if the manager reached its capacity limit
+    pub fn add<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.spawn(f).map(drop)
+    }
+
+    /// spawn a sub coroutine in the manager and return a handle to its result
+    ///
+    /// block the caller if the manager reached its capacity limit
+    pub fn spawn<F, T>(&self, f: F) -> Result<ManagedHandle<T>, Error>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.upgrade()?;
+        inner.acquire_slot();
+        // the manager may start to shut down while waiting for the slot
+        if inner.is_closed() {
+            if let Some(limit) = &inner.limit {
+                limit.post();
+            }
+            return Err(Error::Closed);
+        }
+        Ok(inner
+            .spawn_impl(None, None, None, f)
+            .expect("failed to spawn managed coroutine"))
+    }
+
+    /// return true if the manager is dropped or started to shut down
+    pub fn is_closed(&self) -> bool {
+        self.upgrade().is_err()
+    }
+
+    fn upgrade(&self) -> Result<Arc<Inner>, Error> {
+        self.inner
+            .upgrade()
+            .filter(|inner| !inner.is_closed())
+            .ok_or(Error::Closed)
+    }
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Spawner")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Manager;
+    use may::coroutine;
+    use std::time::Duration;
+
+    #[test]
+    fn spawn_from_threads() {
+        let manager = Manager::new();
+        let spawner = manager.spawner();
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let spawner = spawner.clone();
+                std::thread::spawn(move || spawner.spawn(move || i).unwrap().join().unwrap())
+            })
+            .collect();
+        let sum: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(sum, 6);
+
+        let h = spawner
+            .spawn(|| coroutine::sleep(Duration::from_secs(10)))
+            .unwrap();
+        assert!(!spawner.is_closed());
+        drop(manager);
+        // the sub coroutines are not kept alive by the spawner
+        assert!(h.join().is_err());
+        assert!(spawner.is_closed());
+        assert_eq!(spawner.add(|| {}), Err(Error::Closed));
+    }
+
+    #[test]
+    fn closed_on_shutdown() {
+        let manager = Manager::new();
+        let spawner = manager.spawner();
+        let h = manager.spawn_with_token(|token| token.cancelled());
+        let t = std::thread::spawn(move || manager.shutdown(Duration::from_secs(1)));
+        h.join().unwrap();
+        assert_eq!(spawner.add(|| {}), Err(Error::Closed));
+        t.join().unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+use crate::ExitReason;
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+const BUCKETS: usize = 7;
+
+/// the lifetime histogram of the exited sub coroutines
+///
+/// the bucket `i` counts the lifetimes less than `BOUNDS[i]`,
+/// the last bucket counts the ones that lived longer than all the bounds
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LifetimeHistogram {
+    counts: [u64; BUCKETS],
+}
+
+impl LifetimeHistogram {
+    /// the upper bounds of the buckets
+    pub const BOUNDS: [Duration; BUCKETS - 1] = [
+        Duration::from_millis(1),
+        Duration::from_millis(10),
+        Duration::from_millis(100),
+        Duration::from_secs(1),
+        Duration::from_secs(10),
+        Duration::from_secs(100),
+    ];
+
+    /// the counts of the buckets
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// the total count of the histogram
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    fn bucket(lifetime: Duration) -> usize {
+        Self::BOUNDS
+            .iter()
+            .position(|bound| lifetime < *bound)
+            .unwrap_or(BUCKETS - 1)
+    }
+}
+
+/// a snapshot of the statistics of a manager
+///
+/// the sub coroutines of the sub managers are not counted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManagerStats {
+    /// the number of the spawned sub coroutines
+    pub spawned: u64,
+    /// the number of the running sub coroutines
+    pub alive: usize,
+    /// the peak number of the running sub coroutines
+    pub peak: usize,
+    /// the number of the sub coroutines that returned normally
+    pub completed: u64,
+    /// the number of the sub coroutines that panicked
+    pub panicked: u64,
+    /// the number of the sub coroutines that are cancelled
+    pub cancelled: u64,
+    /// the number of the sub coroutines that ran out of their time budget
+    pub timed_out: u64,
+    /// the number of the sub coroutines that are taken out by `detach`,
+    /// their exits are not counted
+    pub detached: u64,
+    /// the number of the sub coroutines that are moved to another manager,
+    /// their exits are counted by the new manager
+    pub transferred: u64,
+    /// the lifetimes of the exited sub coroutines
+    pub lifetimes: LifetimeHistogram,
+}
+
+// the counters that are updated when the sub coroutines are spawned and exit
+#[derive(Default)]
+pub(crate) struct Stats {
+    spawned: AtomicU64,
+    peak: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    cancelled: AtomicU64,
+    timed_out: AtomicU64,
+    detached: AtomicU64,
+    transferred: AtomicU64,
+    lifetimes: [AtomicU64; BUCKETS],
+}
+
+impl Stats {
+    pub fn spawn(&self, alive: usize) {
+        self.spawned.fetch_add(1, Ordering::Relaxed);
+        self.peak.fetch_max(alive, Ordering::Relaxed);
+    }
+
+    pub fn exit(&self, reason: &ExitReason, lifetime: Duration) {
+        let counter = match reason {
+            ExitReason::Completed => &self.completed,
+            ExitReason::Panicked(_) => &self.panicked,
+            ExitReason::Cancelled => &self.cancelled,
+            ExitReason::TimedOut => &self.timed_out,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.lifetimes[LifetimeHistogram::bucket(lifetime)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn detach(&self) {
+        self.detached.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn transfer(&self) {
+        self.transferred.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, alive: usize) -> ManagerStats {
+        ManagerStats {
+            spawned: self.spawned.load(Ordering::Relaxed),
+            alive,
+            peak: self.peak.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            panicked: self.panicked.load(Ordering::Relaxed),
+            cancelled: self.cancelled.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            detached: self.detached.load(Ordering::Relaxed),
+            transferred: self.transferred.load(Ordering::Relaxed),
+            lifetimes: LifetimeHistogram {
+                counts: self.lifetimes.each_ref().map(|c| c.load(Ordering::Relaxed)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifetime_buckets() {
+        let stats = Stats::default();
+        for ms in [0, 5, 5, 50, 500, 5_000, 50_000, 500_000] {
+            stats.exit(&ExitReason::Completed, Duration::from_millis(ms));
+        }
+        let snapshot = stats.snapshot(0);
+        assert_eq!(snapshot.lifetimes.counts(), [1, 2, 1, 1, 1, 1, 1]);
+        assert_eq!(snapshot.lifetimes.count(), 8);
+        assert_eq!(snapshot.completed, 8);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::{Error, ManagedHandle, Manager};
+use may::coroutine;
+use may::sync::mpsc::{channel, Sender};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// how the supervisor restarts its children when one of them exits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// only restart the exited child
+    OneForOne,
+    /// stop all the other children and restart all of them
+    OneForAll,
+    /// stop the children that started after the exited one,
+    /// and restart them together with the exited one
+    RestForOne,
+}
+
+type ChildFn = Arc<dyn Fn() + Send + Sync>;
+
+// notify the supervisor when the child exits, no matter how it exits
+struct ExitNotify {
+    tx: Sender<(usize, u64)>,
+    idx: usize,
+    generation: u64,
+}
+
+impl Drop for ExitNotify {
+    fn drop(&mut self) {
+        self.tx.send((self.idx, self.generation)).ok();
+    }
+}
+
+/// restart the managed children when they exit or panic
+///
+/// the children are started in the order they are added, each child is a
+/// closure that would be called again for every restart. if the children
+/// are restarted more than the max restart intensity within the period, the
+/// supervisor stops all of its children and fails
+///
+/// ```rust,no_run
+/// use co_managed::{Manager, Strategy, Supervisor};
+/// use std::time::Duration;
+///
+/// let manager = Manager::new();
+/// let supervisor = Supervisor::new(Strategy::OneForOne)
+///     .intensity(5, Duration::from_secs(10))
+///     .backoff(Duration::from_millis(10), Duration::from_secs(1))
+///     .child(|| { /* accept loop */ })
+///     .child(|| { /* cache refresher */ });
+/// // block until the restart intensity is exceeded
+/// let result = supervisor.run_in(&manager);
+/// ```
+pub struct Supervisor {
+    strategy: Strategy,
+    children: Vec<ChildFn>,
+    max_restarts: usize,
+    period: Duration,
+    backoff_initial: Duration,
+    backoff_max: Duration,
+}
+
+impl Supervisor {
+    /// create a supervisor, by default it allows 3 restarts in 5 seconds without backoff
+    pub fn new(strategy: Strategy) -> Self {
+        Supervisor {
+            strategy,
+            children: Vec::new(),
+            max_restarts: 3,
+            period: Duration::from_secs(5),
+            backoff_initial: Duration::ZERO,
+            backoff_max: Duration::ZERO,
+        }
+    }
+
+    /// add a child to the supervisor
+    pub fn child<F>(mut self, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.children.push(Arc::new(f));
+        self
+    }
+
+    /// the supervisor fails if there are more than `max_restarts` restarts within `period`
+    pub fn intensity(mut self, max_restarts: usize, period: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.period = period;
+        self
+    }
+
+    /// delay the restarts exponentially, starting from `initial` and up to `max`
+    ///
+    /// the delay is doubled for each restart within the intensity period
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff_initial = initial;
+        self.backoff_max = max;
+        self
+    }
+
+    /// run the supervisor in the current context
+    ///
+    /// the children belong to a sub manager of `manager`, so they are counted
+    /// in its `tree` and are cancelled with it. it only returns
+    /// `Error::TooManyRestarts` when the restart intensity is exceeded, or
+    /// `Ok(())` if there are no children at all. the children are cancelled
+    /// when the supervisor returns or is cancelled
+    pub fn run_in(self, manager: &Manager) -> Result<(), Error> {
+        let n = self.children.len();
+        if n == 0 {
+            return Ok(());
+        }
+
+        let manager = manager.child();
+        let (tx, rx) = channel();
+        let mut generations = vec![0; n];
+        let mut handles: Vec<_> = (0..n)
+            .map(|idx| Some(self.start(&manager, &tx, idx, 0)))
+            .collect();
+        let mut restarts = VecDeque::new();
+
+        loop {
+            // we hold the sender, the channel is never disconnected
+            let (idx, generation) = rx.recv().expect("supervisor channel closed");
+            if generation != generations[idx] {
+                // the child is stopped by us
+                continue;
+            }
+
+            let now = Instant::now();
+            restarts.push_back(now);
+            while restarts
+                .front()
+                .is_some_and(|t| now.duration_since(*t) > self.period)
+            {
+                restarts.pop_front();
+            }
+            if restarts.len() > self.max_restarts {
+                return Err(Error::TooManyRestarts);
+            }
+
+            let delay = self.delay(restarts.len());
+            if !delay.is_zero() {
+                coroutine::sleep(delay);
+            }
+
+            let range = match self.strategy {
+                Strategy::OneForOne => idx..idx + 1,
+                Strategy::OneForAll => 0..n,
+                Strategy::RestForOne => idx..n,
+            };
+            // stop the children in the reverse start order
+            for i in range.clone().rev() {
+                generations[i] += 1;
+                if let Some(handle) = handles[i].take() {
+                    handle.cancel();
+                    handle.wait();
+                }
+            }
+            for i in range {
+                handles[i] = Some(self.start(&manager, &tx, i, generations[i]));
+            }
+        }
+    }
+
+    fn start(
+        &self,
+        manager: &Manager,
+        tx: &Sender<(usize, u64)>,
+        idx: usize,
+        generation: u64,
+    ) -> ManagedHandle<()> {
+        let f = self.children[idx].clone();
+        let notify = ExitNotify {
+            tx: tx.clone(),
+            idx,
+            generation,
+        };
+        manager.spawn(move || {
+            let _notify = notify;
+            f()
+        })
+    }
+
+    // the delay before the nth restart within the period
+    fn delay(&self, nth: usize) -> Duration {
+        let shift = nth.saturating_sub(1).min(31) as u32;
+        self.backoff_initial
+            .saturating_mul(1 << shift)
+            .min(self.backoff_max)
+    }
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("strategy", &self.strategy)
+            .field("children", &self.children.len())
+            .field("max_restarts", &self.max_restarts)
+            .field("period", &self.period)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counter() -> Arc<AtomicUsize> {
+        Arc::new(AtomicUsize::new(0))
+    }
+
+    // a child that counts its starts and exits for the first `exits` times
+    fn child(starts: &Arc<AtomicUsize>, exits: usize) -> impl Fn() + Send + Sync + 'static {
+        let starts = starts.clone();
+        move || {
+            if starts.fetch_add(1, Ordering::SeqCst) < exits {
+                coroutine::sleep(Duration::from_millis(10));
+                panic!("child exit");
+            }
+            loop {
+                coroutine::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    fn run_for(supervisor: Supervisor, dur: Duration) {
+        let n = supervisor.children.len();
+        let manager = Arc::new(Manager::new());
+        let m = manager.clone();
+        let h = may::go!(move || supervisor.run_in(&m));
+        coroutine::sleep(dur);
+        assert!(!h.is_done());
+        // the children are in the sub manager
+        assert_eq!(manager.tree().coroutines, 0);
+        assert_eq!(manager.tree().children[0].coroutines, n);
+        unsafe { h.coroutine().cancel() };
+        h.join().ok();
+        assert!(manager.tree().children.is_empty());
+    }
+
+    #[test]
+    fn one_for_one() {
+        let (a, b) = (counter(), counter());
+        let supervisor = Supervisor::new(Strategy::OneForOne)
+            .child(child(&a, 2))
+            .child(child(&b, 0));
+        run_for(supervisor, Duration::from_millis(200));
+        assert_eq!(a.load(Ordering::SeqCst), 3);
+        assert_eq!(b.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn one_for_all() {
+        let (a, b) = (counter(), counter());
+        let supervisor = Supervisor::new(Strategy::OneForAll)
+            .child(child(&a, 0))
+            .child(child(&b, 1));
+        run_for(supervisor, Duration::from_millis(200));
+        assert_eq!(a.load(Ordering::SeqCst), 2);
+        assert_eq!(b.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn rest_for_one() {
+        let (a, b, c) = (counter(), counter(), counter());
+        let supervisor = Supervisor::new(Strategy::RestForOne)
+            .child(child(&a, 0))
+            .child(child(&b, 1))
+            .child(child(&c, 0));
+        run_for(supervisor, Duration::from_millis(200));
+        assert_eq!(a.load(Ordering::SeqCst), 1);
+        assert_eq!(b.load(Ordering::SeqCst), 2);
+        assert_eq!(c.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn restart_intensity() {
+        let a = counter();
+        let supervisor = Supervisor::new(Strategy::OneForOne)
+            .intensity(3, Duration::from_secs(10))
+            .backoff(Duration::from_millis(20), Duration::from_millis(30))
+            .child(child(&a, usize::MAX));
+        let start = Instant::now();
+        assert_eq!(
+            supervisor.run_in(&Manager::new()),
+            Err(Error::TooManyRestarts)
+        );
+        // 3 restarts delayed by 20ms, 30ms and 30ms
+        assert!(start.elapsed() >= Duration::from_millis(80));
+        assert_eq!(a.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn no_children() {
+        let manager = Manager::new();
+        assert_eq!(
+            Supervisor::new(Strategy::OneForAll).run_in(&manager),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{CoNode, Node};
+use may::coroutine::{self, Coroutine};
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+// the deadline of a sub coroutine, the earliest one is on the top of the heap
+struct Deadline {
+    at: Instant,
+    node: Weak<Node>,
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Deadline {}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Deadline {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.at.cmp(&self.at)
+    }
+}
+
+#[derive(Default)]
+struct Deadlines {
+    heap: BinaryHeap<Deadline>,
+    // prune the finished sub coroutines when the heap grows to this size
+    prune_at: usize,
+}
+
+/// cancel the sub coroutines when their deadlines expire
+///
+/// there is only one watchdog coroutine for each manager, it's spawned when
+/// the first deadline is added and exits when the timer is closed
+#[derive(Default)]
+pub(crate) struct Timer {
+    deadlines: Mutex<Deadlines>,
+    watchdog: Mutex<Option<Coroutine>>,
+    closed: AtomicBool,
+}
+
+impl Timer {
+    pub fn add(self: &Arc<Self>, at: Instant, node: &CoNode) {
+        let earliest = {
+            let mut deadlines = self.deadlines.lock().unwrap();
+            if deadlines.heap.len() >= deadlines.prune_at {
+                deadlines.heap.retain(|d| d.node.strong_count() > 0);
+                deadlines.prune_at = (deadlines.heap.len() * 2).max(64);
+            }
+            deadlines.heap.push(Deadline {
+                at,
+                node: Arc::downgrade(node),
+            });
+            deadlines.heap.peek().is_some_and(|d| d.at == at)
+        };
+
+        let mut watchdog = self.watchdog.lock().unwrap();
+        match &*watchdog {
+            Some(co) if earliest => co.unpark(),
+            Some(_) => {}
+            None => {
+                let timer = self.clone();
+                let co = go!(move || timer.run());
+                *watchdog = Some(co.coroutine().clone());
+            }
+        }
+    }
+
+    // stop the watchdog, the pending deadlines are dropped
+    pub fn close(&self) {
+        self.closed.store(true, atomic::Ordering::Release);
+        if let Some(co) = &*self.watchdog.lock().unwrap() {
+            co.unpark();
+        }
+    }
+
+    fn run(&self) {
+        let mut expired = Vec::new();
+        while !self.closed.load(atomic::Ordering::Acquire) {
+            let now = Instant::now();
+            let next = {
+                let mut deadlines = self.deadlines.lock().unwrap();
+                while deadlines.heap.peek().is_some_and(|d| d.at <= now) {
+                    expired.extend(deadlines.heap.pop());
+                }
+                deadlines.heap.peek().map(|d| d.at)
+            };
+
+            for deadline in expired.drain(..) {
+                if let Some(node) = deadline.node.upgrade() {
+                    if let Node::Co(child) = &*node {
+                        child.time_out();
+                    }
+                }
+            }
+
+            // the park timeout is truncated to milliseconds and a zero timeout
+            // never fires, it may wake up early and re-check the deadlines
+            match next {
+                Some(at) => coroutine::park_timeout(
+                    at.saturating_duration_since(now)
+                        .max(Duration::from_millis(1)),
+                ),
+                None => coroutine::park(),
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use may::sync::SyncFlag;
+
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+#[derive(Default)]
+struct TokenInner {
+    flag: SyncFlag,
+    children: Mutex<Vec<Weak<TokenInner>>>,
+}
+
+impl TokenInner {
+    fn cancel(&self) {
+        if self.flag.is_fired() {
+            return;
+        }
+        self.flag.fire();
+        let children = std::mem::take(&mut *self.children.lock().unwrap());
+        children
+            .iter()
+            .filter_map(Weak::upgrade)
+            .for_each(|child| child.cancel());
+    }
+}
+
+/// a token used to cooperatively cancel managed sub coroutines
+///
+/// cancelling a token would also cancel all its child tokens,
+/// the sub coroutines should check the token and exit at a safe point
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<TokenInner>,
+}
+
+impl CancelToken {
+    /// create a new token that is not cancelled
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// create a child token that is cancelled when this token is cancelled
+    ///
+    /// cancel the child token would not affect this token
+    pub fn child_token(&self) -> CancelToken {
+        let child = CancelToken::new();
+        let mut children = self.inner.children.lock().unwrap();
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            children.retain(|c| c.strong_count() > 0);
+            children.push(Arc::downgrade(&child.inner));
+        }
+        child
+    }
+
+    /// cancel the token and all its child tokens
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    /// return true if the token is cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.flag.is_fired()
+    }
+
+    /// block until the token is cancelled
+    ///
+    /// this works for both threads and coroutines, so it can be used as a
+    /// branch of `select!`
+    pub fn cancelled(&self) {
+        self.inner.flag.wait();
+    }
+
+    /// same as `cancelled` except that with a timeout
+    /// return false if timeout happened
+    pub fn cancelled_timeout(&self, dur: Duration) -> bool {
+        self.inner.flag.wait_timeout(dur)
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use may::coroutine;
+
+    #[test]
+    fn child_token() {
+        let parent = CancelToken::new();
+        let child = parent.child_token();
+        let grand_child = child.child_token();
+
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(grand_child.is_cancelled());
+        assert!(!parent.is_cancelled());
+
+        let child = parent.child_token();
+        parent.cancel();
+        assert!(child.is_cancelled());
+        // child of a cancelled token is cancelled
+        assert!(parent.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn cancelled_wait() {
+        let token = CancelToken::new();
+        assert!(!token.cancelled_timeout(Duration::from_millis(10)));
+
+        let token_dup = token.clone();
+        let j = go!(move || token_dup.cancelled());
+        coroutine::sleep(Duration::from_millis(10));
+        assert!(!j.is_done());
+        token.cancel();
+        j.join().unwrap();
+        assert!(token.cancelled_timeout(Duration::from_millis(10)));
+    }
+}
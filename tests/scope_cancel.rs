@@ -0,0 +1,47 @@
+// the cancelled caller of `scope` blocks its worker thread,
+// it needs another worker to run the sub coroutines
+use may::coroutine;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn scope_cancelled_caller() {
+    may::config().set_workers(2);
+
+    let progress = Arc::new(AtomicUsize::new(0));
+    let exited = Arc::new(AtomicUsize::new(0));
+    let (progress_dup, exited_dup) = (progress.clone(), exited.clone());
+    let j = unsafe {
+        coroutine::spawn(move || {
+            let local = AtomicUsize::new(0);
+            co_managed::scope(|s| {
+                s.add(|| {
+                    struct Exit<'a>(&'a AtomicUsize, &'a AtomicUsize);
+                    impl Drop for Exit<'_> {
+                        fn drop(&mut self) {
+                            // the borrowed local is still alive
+                            self.1
+                                .store(self.0.load(Ordering::SeqCst), Ordering::SeqCst);
+                        }
+                    }
+                    let _exit = Exit(&local, &exited_dup);
+                    loop {
+                        local.fetch_add(1, Ordering::SeqCst);
+                        progress_dup.fetch_add(1, Ordering::SeqCst);
+                        coroutine::sleep(Duration::from_millis(5));
+                    }
+                });
+                coroutine::sleep(Duration::from_secs(10));
+            });
+        })
+    };
+    while progress.load(Ordering::SeqCst) < 3 {
+        coroutine::sleep(Duration::from_millis(5));
+    }
+    unsafe { j.coroutine().cancel() };
+    assert!(j.join().is_err());
+    // the sub coroutine is joined before the caller exits
+    assert!(exited.load(Ordering::SeqCst) >= 3);
+}
@@ -0,0 +1,95 @@
+// the sub coroutines run on the worker threads, it needs a global subscriber
+#![cfg(feature = "tracing")]
+use co_managed::Manager;
+use may::coroutine;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// record the events as "span: message name"
+struct Collect(Arc<Mutex<Vec<String>>>);
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    name: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "name" {
+            self.name = value.to_string();
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        }
+    }
+}
+
+impl<S> Layer<S> for Collect
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let span = ctx.event_span(event).map_or("none", |s| s.name());
+        let msg = format!("{span}: {} {}", fields.message, fields.name);
+        self.0.lock().unwrap().push(msg);
+    }
+}
+
+#[test]
+fn tracing_events() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = tracing_subscriber::registry().with(Collect(log.clone()));
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    let manager = Manager::new();
+    tracing::info_span!("request").in_scope(|| {
+        manager
+            .builder()
+            .name("worker")
+            .spawn(|| co_managed::current_span().in_scope(|| tracing::info!("in child")))
+            .unwrap();
+        manager
+            .builder()
+            .name("sleeper")
+            .spawn(|| coroutine::sleep(Duration::from_secs(10)))
+            .unwrap();
+    });
+    // the unrelated coroutines on the same worker don't see the span
+    let unrelated: Vec<_> = (0..10)
+        .map(|_| may::go!(|| tracing::info!("unrelated")))
+        .collect();
+    unrelated.into_iter().for_each(|h| h.join().unwrap());
+    manager.wait_idle(Duration::from_millis(50));
+    drop(manager);
+
+    let log = log.lock().unwrap();
+    let expected = [
+        "request: managed coroutine spawned worker",
+        "request: in child ",
+        "request: managed coroutine exited worker",
+        "request: managed coroutine spawned sleeper",
+        "request: managed coroutine cancelled sleeper",
+        "request: managed coroutine exited sleeper",
+    ];
+    for msg in expected {
+        assert!(log.iter().any(|m| m == msg), "{msg} not in {log:?}");
+    }
+    let unrelated: Vec<_> = log.iter().filter(|m| m.ends_with("unrelated ")).collect();
+    assert_eq!(unrelated.len(), 10);
+    assert!(
+        unrelated.iter().all(|m| *m == "none: unrelated "),
+        "{log:?}"
+    );
+}